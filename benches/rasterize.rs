@@ -0,0 +1,103 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use gridvid::{
+    backend::{FrameFormat, VideoBackend, VideoInfo},
+    Encoder, Gridlines, Result, Scaling,
+};
+use std::time::Duration;
+
+/// Discards frames, so that only rasterization is measured.
+struct NullBackend(FrameFormat);
+
+impl VideoBackend for NullBackend {
+    fn frame_format(&self) -> FrameFormat {
+        self.0
+    }
+
+    fn init(&mut self, _info: &VideoInfo) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_frame(&mut self, rgb: &[u8], _duration: Duration) -> Result<()> {
+        black_box(rgb);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn grid(width: usize, height: usize) -> Vec<Vec<bool>> {
+    (0..width)
+        .map(|x| (0..height).map(|y| (x * 7 + y * 13) % 5 == 0).collect())
+        .collect()
+}
+
+fn converter(cell: &bool) -> (u8, u8, u8) {
+    if *cell {
+        (255, 255, 255)
+    } else {
+        (0, 0, 0)
+    }
+}
+
+fn snapshot(c: &mut Criterion) {
+    let large = grid(1000, 1000);
+    c.bench_function("snapshot 1000x1000 uniform(1) gridlines", |b| {
+        b.iter(|| {
+            gridvid::snapshot(
+                black_box(&large),
+                converter,
+                Scaling::Uniform(1),
+                Gridlines::Show((40, 40, 40)),
+            )
+            .unwrap()
+        })
+    });
+
+    let small = grid(100, 100);
+    c.bench_function("snapshot 100x100 uniform(8)", |b| {
+        b.iter(|| {
+            gridvid::snapshot(
+                black_box(&small),
+                converter,
+                Scaling::Uniform(8),
+                Gridlines::Hide,
+            )
+            .unwrap()
+        })
+    });
+}
+
+fn add_frame(c: &mut Criterion) {
+    let grid = grid(500, 500);
+    for (name, format) in [("rgb", FrameFormat::Rgb), ("yuv420", FrameFormat::Yuv420)] {
+        let mut encoder = Encoder::with_backend(NullBackend(format), Box::new(converter))
+            .scale(Scaling::Uniform(2))
+            .gridlines(Gridlines::Show((40, 40, 40)))
+            .build()
+            .unwrap();
+
+        c.bench_function(
+            &format!("add_frame {name} 500x500 uniform(2) gridlines"),
+            |b| b.iter(|| encoder.add_frame(black_box(&grid)).unwrap()),
+        );
+
+        #[cfg(feature = "rayon")]
+        {
+            let mut encoder = Encoder::with_backend_par(NullBackend(format), Box::new(converter))
+                .scale(Scaling::Uniform(2))
+                .gridlines(Gridlines::Show((40, 40, 40)))
+                .build()
+                .unwrap();
+
+            c.bench_function(
+                &format!("add_frame_par {name} 500x500 uniform(2) gridlines"),
+                |b| b.iter(|| encoder.add_frame_par(black_box(&grid)).unwrap()),
+            );
+        }
+    }
+}
+
+criterion_group!(benches, snapshot, add_frame);
+criterion_main!(benches);
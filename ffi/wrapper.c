@@ -10,11 +10,10 @@ Source: https://github.com/lieff/minimp4/blob/master/minimp4_test.c
 #ifdef _WIN32
 #include <sys/types.h>
 #include <stddef.h>
-typedef size_t ssize_t;
 #endif
 
 #define MINIMP4_IMPLEMENTATION
-#include "minimp4.h"
+#include "wrapper.h"
 
 static ssize_t get_nal_size(uint8_t *buf, ssize_t size)
 {
@@ -30,28 +29,46 @@ static ssize_t get_nal_size(uint8_t *buf, ssize_t size)
     return size;
 }
 
-static int write_callback(int64_t offset, const void *buffer, size_t size, void *token)
+struct mp4_muxer
 {
-    FILE *f = (FILE*)token;
-    fseek(f, offset, SEEK_SET);
-    return fwrite(buffer, 1, size, f) != size;
-}
+    MP4E_mux_t *mux;
+    mp4_h26x_writer_t writer;
+};
 
-void mux_mp4(char *filename, uint8_t *h264_buf, ssize_t h264_size, int width, int height, int fps) {
-    FILE *file_ptr;
-    file_ptr = fopen(filename, "wb");
+int mp4_mux_open(mp4_muxer_t **muxer, write_callback_t write_callback, void *token, int width, int height, int fragmented) {
+    int err = MP4E_STATUS_OK;
+    *muxer = NULL;
 
-    MP4E_mux_t *mux;
-    mux = MP4E_open(0, 0, file_ptr, write_callback);
+    mp4_muxer_t *m = (mp4_muxer_t*)malloc(sizeof(mp4_muxer_t));
+    if (!m)
+        return MP4E_STATUS_NO_MEMORY;
 
-    mp4_h26x_writer_t mp4wr;
-    if (MP4E_STATUS_OK != mp4_h26x_write_init(&mp4wr, mux, width, height, 0))
+    m->mux = MP4E_open(0, fragmented, token, write_callback);
+    if (!m->mux)
     {
-        printf("error: mp4_h26x_write_init failed\n");
-        exit(1);
+        free(m);
+        return MP4E_STATUS_FILE_WRITE_ERROR;
     }
 
-        while (h264_size > 0)
+    err = mp4_h26x_write_init(&m->writer, m->mux, width, height, 0);
+    if (MP4E_STATUS_OK == err && m->writer.mux_track_id < 0)
+        err = m->writer.mux_track_id;
+    if (MP4E_STATUS_OK != err)
+    {
+        MP4E_close(m->mux);
+        mp4_h26x_write_close(&m->writer);
+        free(m);
+        return err;
+    }
+
+    *muxer = m;
+    return MP4E_STATUS_OK;
+}
+
+int mp4_mux_write(mp4_muxer_t *muxer, uint8_t *h264_buf, ssize_t h264_size, unsigned duration) {
+    int err = MP4E_STATUS_OK;
+
+    while (MP4E_STATUS_OK == err && h264_size > 0)
     {
         ssize_t nal_size = get_nal_size(h264_buf, h264_size);
         if (nal_size < 4)
@@ -61,17 +78,16 @@ void mux_mp4(char *filename, uint8_t *h264_buf, ssize_t h264_size, int width, in
             continue;
         }
 
-        if (MP4E_STATUS_OK != mp4_h26x_write_nal(&mp4wr, h264_buf, nal_size, 90000 / fps))
-        {
-            printf("error: mp4_h26x_write_nal failed\n");
-            exit(1);
-        }
+        err = mp4_h26x_write_nal(&muxer->writer, h264_buf, nal_size, duration);
         h264_buf  += nal_size;
         h264_size -= nal_size;
-
-
     }
-    MP4E_close(mux);
-    mp4_h26x_write_close(&mp4wr);
-    fclose(file_ptr);
+    return err;
+}
+
+int mp4_mux_close(mp4_muxer_t *muxer) {
+    int err = MP4E_close(muxer->mux);
+    mp4_h26x_write_close(&muxer->writer);
+    free(muxer);
+    return err;
 }
@@ -4,4 +4,18 @@ typedef size_t ssize_t;
 
 #include "minimp4.h"
 
-void mux_mp4(char *filename, uint8_t *h264_buf, ssize_t h264_size, int width, int height, int fps);
+// Matches the minimp4 write callback. Returns 0 on success.
+typedef int (*write_callback_t)(int64_t offset, const void *buffer, size_t size, void *token);
+
+// Incremental H.264 MP4 muxer. Functions return MP4E_STATUS_OK, or the first error status returned by minimp4.
+typedef struct mp4_muxer mp4_muxer_t;
+
+// Writes the MP4 header and prepares a single H.264 track.
+// If `fragmented` is nonzero, each frame is written as its own fragment.
+int mp4_mux_open(mp4_muxer_t **muxer, write_callback_t write_callback, void *token, int width, int height, int fragmented);
+
+// Writes the Annex-B NAL units of a single frame, with its duration in 1/90000 s.
+int mp4_mux_write(mp4_muxer_t *muxer, uint8_t *h264_buf, ssize_t h264_size, unsigned duration);
+
+// Writes the MP4 index and frees the muxer.
+int mp4_mux_close(mp4_muxer_t *muxer);
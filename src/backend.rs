@@ -0,0 +1,237 @@
+//! Output backends for the video [Encoder](crate::Encoder).
+//!
+//! The [Encoder](crate::Encoder) handles grid validation, scaling and gridlines, then hands each
+//! rasterized frame to a [VideoBackend]. The default backend, [Mp4], encodes frames with OpenH264
+//! and muxes them into an MP4 file with minimp4.
+//!
+//! Custom output formats can be supported by implementing [VideoBackend] and passing it to
+//! [Encoder::with_backend](crate::Encoder::with_backend).
+//!
+//! Any backend can be moved to a worker thread with [Background], so that encoding overlaps
+//! with computing the next grid.
+use crate::{Error, RateControl, Result};
+use std::{fs, io, path::Path, time::Duration};
+
+mod background;
+#[cfg(feature = "gif")]
+mod gif;
+mod h264;
+mod mp4;
+#[cfg(feature = "png")]
+mod png;
+mod y4m;
+
+#[cfg(feature = "gif")]
+#[doc(inline)]
+pub use self::gif::Gif;
+#[cfg(feature = "png")]
+pub(crate) use self::png::write_png;
+#[cfg(feature = "png")]
+#[doc(inline)]
+pub use self::png::PngSequence;
+#[doc(inline)]
+pub use background::Background;
+#[doc(inline)]
+pub use h264::H264;
+#[doc(inline)]
+pub use mp4::Mp4;
+#[doc(inline)]
+pub use y4m::Y4m;
+
+/// Video properties passed to a [VideoBackend] before the first frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VideoInfo {
+    /// Frame width in pixels.
+    pub width: usize,
+    /// Frame height in pixels.
+    pub height: usize,
+    /// Frame rate in frames per second.
+    pub fps: FrameRate,
+    /// H.264 encoder settings. Backends that do not encode H.264 ignore them.
+    pub h264: H264Settings,
+}
+
+/// A frame rate of `num / den` frames per second, e.g. `30000 / 1001` for 29.97 fps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRate {
+    /// Frames per `den` seconds.
+    pub num: u32,
+    /// Seconds per `num` frames.
+    pub den: u32,
+}
+
+impl FrameRate {
+    /// Returns the frame rate as a floating point number of frames per second.
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Returns the start time of frame `n`, in units of `1 / timescale` seconds, rounded to the
+    /// nearest unit.
+    ///
+    /// Frame durations taken as the difference between consecutive start times do not drift,
+    /// even when a single frame does not last a whole number of units.
+    pub fn timestamp(&self, n: u64, timescale: u32) -> u64 {
+        let (num, den) = (self.num as u128, self.den as u128);
+        ((n as u128 * den * timescale as u128 * 2 + num) / (num * 2)) as u64
+    }
+}
+
+/// Converts frame durations to whole units of a time base without cumulative drift.
+///
+/// Each frame lasts from its rounded start time to its rounded end time, so rounding errors
+/// do not add up over the length of the video.
+pub(crate) struct Timeline {
+    elapsed: Duration,
+    units: FrameRate,
+}
+
+impl Timeline {
+    /// Returns a timeline counting `units.num / units.den` units per second.
+    pub(crate) fn new(units: FrameRate) -> Self {
+        Timeline {
+            elapsed: Duration::ZERO,
+            units,
+        }
+    }
+
+    /// Advances the timeline by `duration`, returning the duration in whole units.
+    pub(crate) fn advance(&mut self, duration: Duration) -> u64 {
+        let start = self.position();
+        self.elapsed += duration;
+        self.position() - start
+    }
+
+    fn position(&self) -> u64 {
+        let (num, den) = (self.units.num as u128, self.units.den as u128);
+        let scale = den * 1_000_000_000;
+        ((self.elapsed.as_nanos() * num * 2 + scale) / (scale * 2)) as u64
+    }
+}
+
+/// Pixel layout of the frames passed to [VideoBackend::write_frame].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FrameFormat {
+    /// Packed RGB, `width * height * 3` bytes.
+    #[default]
+    Rgb,
+    /// Planar YUV 4:2:0 (I420): a `width * height` Y plane followed by `width * height / 4`
+    /// byte U and V planes.
+    ///
+    /// Limited range BT.601, with chroma averaged over each 2x2 block. The conversion is
+    /// identical to `openh264::formats::YUVBuffer::with_rgb`. Frame width and height are
+    /// multiples of 2: the [Encoder](crate::Encoder) returns
+    /// [Error::InvalidFrameDimensions] for other sizes,
+    /// before calling [init](VideoBackend::init).
+    Yuv420,
+}
+
+/// H.264 encoder settings, set through the [EncoderBuilder](crate::EncoderBuilder).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct H264Settings {
+    /// Rate control mode.
+    pub rate_control: RateControl,
+    /// Target bitrate in bits per second. `None` uses the OpenH264 default.
+    pub bitrate: Option<u32>,
+    /// Minimum and maximum quantization parameters. `None` uses the OpenH264 defaults.
+    pub qp_range: Option<(u8, u8)>,
+    /// Number of frames between IDR keyframes. `None` only inserts keyframes at the start of
+    /// the video and at scene changes detected by OpenH264.
+    pub keyframe_interval: Option<u32>,
+}
+
+/// A destination for rasterized video frames.
+///
+/// Methods are called by the [Encoder](crate::Encoder) in this order:
+/// 1. [open](VideoBackend::open), then [frame_format](VideoBackend::frame_format), once, from
+///    [EncoderBuilder::build](crate::EncoderBuilder::build).
+/// 2. [init](VideoBackend::init) once, when the first frame's dimensions are known.
+/// 3. [write_frame](VideoBackend::write_frame) for every frame.
+/// 4. [finish](VideoBackend::finish) once, from [Encoder::close](crate::Encoder::close).
+pub trait VideoBackend {
+    /// Validates and prepares the output destination.
+    ///
+    /// Default: does nothing.
+    fn open(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Configures the backend for the video dimensions and frame rate.
+    ///
+    /// Backends with constraints on frame dimensions should return an error here.
+    fn init(&mut self, info: &VideoInfo) -> Result<()>;
+
+    /// Returns the pixel layout this backend receives frames in.
+    ///
+    /// Called once, after [open](VideoBackend::open). Backends that encode YUV should request
+    /// [FrameFormat::Yuv420], which the [Encoder](crate::Encoder) rasterizes directly from the grid.
+    ///
+    /// Default: [FrameFormat::Rgb]
+    fn frame_format(&self) -> FrameFormat {
+        FrameFormat::Rgb
+    }
+
+    /// Receives a single frame in the [FrameFormat] returned by
+    /// [frame_format](VideoBackend::frame_format), packed RGB by default.
+    ///
+    /// Rows are ordered from top to bottom, pixels from left to right.
+    ///
+    /// `duration` is how long the frame is shown: one frame interval at [VideoInfo::fps], or the
+    /// duration passed to [Encoder::add_frame_with_duration](crate::Encoder::add_frame_with_duration).
+    /// Frame intervals are rounded to the nanosecond such that the total duration does not drift.
+    fn write_frame(&mut self, frame: &[u8], duration: Duration) -> Result<()>;
+
+    /// Requests that the next frame is written as a keyframe.
+    ///
+    /// Called by [Encoder::force_keyframe](crate::Encoder::force_keyframe), between frames.
+    ///
+    /// Default: does nothing, for formats that store every frame independently.
+    fn force_keyframe(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Finalizes the output after the last frame.
+    fn finish(&mut self) -> Result<()>;
+
+    /// Returns whether the backend writes to memory, so that [take_bytes](VideoBackend::take_bytes)
+    /// returns the output after [finish](VideoBackend::finish).
+    ///
+    /// Checked by [Encoder::close_to_vec](crate::Encoder::close_to_vec) before finishing the output.
+    ///
+    /// Default: `false`
+    fn writes_to_memory(&self) -> bool {
+        false
+    }
+
+    /// Returns the finished output if the backend writes to memory.
+    ///
+    /// Called by [Encoder::close_to_vec](crate::Encoder::close_to_vec) after [finish](VideoBackend::finish).
+    ///
+    /// Default: `None`
+    fn take_bytes(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Returns an error if the output file already exists.
+pub(crate) fn ensure_new_file(filepath: &Path) -> Result<()> {
+    if Path::try_exists(filepath)? {
+        return Err(Error::IoError(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("output file already exists: {}", filepath.display()),
+        )));
+    }
+    Ok(())
+}
+
+/// Creates a new output file, returning an error if it already exists.
+pub(crate) fn create_file(filepath: &Path) -> Result<fs::File> {
+    ensure_new_file(filepath)?;
+
+    let file = fs::File::create(filepath)?;
+    log::debug!("video output file created: {}", filepath.display());
+    Ok(file)
+}
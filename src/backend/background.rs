@@ -0,0 +1,248 @@
+use super::{FrameFormat, VideoBackend, VideoInfo};
+use crate::{Error, Result};
+use std::{
+    io,
+    sync::mpsc::{self, Receiver, Sender, SyncSender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const DEFAULT_QUEUE_LEN: usize = 4;
+
+/// Creates the wrapped backend on the worker thread.
+type Factory = Box<dyn FnOnce() -> Box<dyn VideoBackend> + Send>;
+
+/// Output of the worker thread: the backend's in-memory output, if any.
+type Worker = JoinHandle<Result<Option<Vec<u8>>>>;
+
+/// Runs another [VideoBackend] on a worker thread, so that encoding overlaps with the caller
+/// computing and adding the next grid.
+///
+/// [Encoder::add_frame](crate::Encoder::add_frame) rasterizes the grid and queues a copy of the
+/// frame. The queue holds at most [queue_len](Background::queue_len) frames; when it is full,
+/// `add_frame` waits for the worker to catch up, so memory use does not grow without limit.
+///
+/// The wrapped backend is created on the worker thread by a closure, and never leaves it.
+/// Errors from [open](VideoBackend::open) are returned by
+/// [EncoderBuilder::build](crate::EncoderBuilder::build), and errors from
+/// [init](VideoBackend::init) by the first [add_frame](crate::Encoder::add_frame). Later errors
+/// from the worker are returned by the next [add_frame](crate::Encoder::add_frame) or by
+/// [close](crate::Encoder::close).
+///
+/// ```no_run
+/// # fn main() -> gridvid::Result<()> {
+/// use gridvid::{backend, Encoder};
+///
+/// # let convert = |&b: &bool| if b { (255, 255, 255) } else { (0, 0, 0) };
+/// let mut video = Encoder::with_backend(
+///     backend::Background::new(|| backend::Mp4::new("/tmp/output.mp4")),
+///     Box::new(convert),
+/// )
+/// .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Background {
+    factory: Option<Factory>,
+    queue_len: usize,
+    frame_format: FrameFormat,
+    writes_to_memory: bool,
+    requests: Option<SyncSender<Request>>,
+    /// Frame buffers returned by the worker, for reuse.
+    recycled: Option<Receiver<Vec<u8>>>,
+    worker: Option<Worker>,
+    bytes: Option<Vec<u8>>,
+}
+
+enum Request {
+    /// Initializes the backend and replies with the result.
+    Init(VideoInfo, SyncSender<Result<()>>),
+    Frame(Vec<u8>, Duration),
+    ForceKeyframe,
+    Finish,
+}
+
+impl Background {
+    /// Returns a new background backend, running the backend returned by `backend` on a worker thread.
+    pub fn new<B, F>(backend: F) -> Self
+    where
+        B: VideoBackend + 'static,
+        F: FnOnce() -> B + Send + 'static,
+    {
+        Background {
+            factory: Some(Box::new(move || Box::new(backend()))),
+            queue_len: DEFAULT_QUEUE_LEN,
+            frame_format: FrameFormat::Rgb,
+            writes_to_memory: false,
+            requests: None,
+            recycled: None,
+            worker: None,
+            bytes: None,
+        }
+    }
+
+    /// Sets the maximum number of frames waiting for the worker thread.
+    ///
+    /// Default: `4`
+    pub fn queue_len(mut self, queue_len: usize) -> Self {
+        self.queue_len = queue_len;
+        self
+    }
+
+    /// Sends a request to the worker. If the worker has stopped, returns its error instead.
+    ///
+    /// Once the worker's error has been returned, every later request fails.
+    fn send(&mut self, request: Request) -> Result<()> {
+        let Some(requests) = self.requests.as_ref() else {
+            return Err(stopped());
+        };
+        let stopped = self
+            .worker
+            .as_ref()
+            .is_some_and(|worker| worker.is_finished());
+        if stopped || requests.send(request).is_err() {
+            self.join()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the worker to stop, returning its error or storing its output.
+    fn join(&mut self) -> Result<()> {
+        self.requests = None;
+        let Some(worker) = self.worker.take() else {
+            return Ok(());
+        };
+        match worker.join() {
+            Ok(result) => {
+                self.bytes = result?;
+                Ok(())
+            }
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl VideoBackend for Background {
+    fn open(&mut self) -> Result<()> {
+        let factory = self.factory.take().unwrap();
+        let (requests, queue) = mpsc::sync_channel(self.queue_len);
+        let (ready, opened) = mpsc::sync_channel(1);
+        let (recycle, recycled) = mpsc::channel();
+
+        let worker = thread::spawn(move || {
+            let mut backend = factory();
+            let opened = backend
+                .open()
+                .map(|_| (backend.frame_format(), backend.writes_to_memory()));
+            let failed = opened.is_err();
+            let _ = ready.send(opened);
+            if failed {
+                return Ok(None);
+            }
+            run(backend, queue, recycle)
+        });
+
+        (self.frame_format, self.writes_to_memory) = match opened.recv() {
+            Ok(opened) => opened?,
+            // The worker panicked before opening the backend
+            Err(_) => match worker.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(_) => unreachable!("worker reports the result of open"),
+            },
+        };
+        self.requests = Some(requests);
+        self.recycled = Some(recycled);
+        self.worker = Some(worker);
+        Ok(())
+    }
+
+    fn frame_format(&self) -> FrameFormat {
+        self.frame_format
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        // Waits for the result, so that invalid dimensions are rejected before frames are rasterized
+        let (reply, initialized) = mpsc::sync_channel(1);
+        self.send(Request::Init(*info, reply))?;
+        match initialized.recv() {
+            Ok(result) => result,
+            // The worker stopped before replying
+            Err(_) => {
+                self.join()?;
+                Err(stopped())
+            }
+        }
+    }
+
+    fn write_frame(&mut self, frame: &[u8], duration: Duration) -> Result<()> {
+        let mut buffer = self
+            .recycled
+            .as_ref()
+            .and_then(|recycled| recycled.try_recv().ok())
+            .unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+        self.send(Request::Frame(buffer, duration))
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.send(Request::ForceKeyframe)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.send(Request::Finish)?;
+        self.join()
+    }
+
+    fn writes_to_memory(&self) -> bool {
+        self.writes_to_memory
+    }
+
+    fn take_bytes(&mut self) -> Option<Vec<u8>> {
+        self.bytes.take()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        // Closing the queue stops the worker. Errors are discarded, as after a failed write.
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Error returned after the worker has stopped.
+fn stopped() -> Error {
+    Error::IoError(io::Error::other(
+        "background worker stopped after an earlier error",
+    ))
+}
+
+/// Passes requests to the backend until the video is finished or an error occurs.
+fn run(
+    mut backend: Box<dyn VideoBackend>,
+    queue: Receiver<Request>,
+    recycle: Sender<Vec<u8>>,
+) -> Result<Option<Vec<u8>>> {
+    // The queue closes without `Finish` if the encoder is dropped before it is closed
+    while let Ok(request) = queue.recv() {
+        match request {
+            Request::Init(info, reply) => {
+                // A failed init is returned to the caller, which can retry it
+                let _ = reply.send(backend.init(&info));
+            }
+            Request::Frame(frame, duration) => {
+                backend.write_frame(&frame, duration)?;
+                let _ = recycle.send(frame);
+            }
+            Request::ForceKeyframe => backend.force_keyframe()?,
+            Request::Finish => {
+                backend.finish()?;
+                return Ok(backend.take_bytes());
+            }
+        }
+    }
+    Ok(None)
+}
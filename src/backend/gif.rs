@@ -0,0 +1,185 @@
+use super::{FrameRate, Timeline, VideoBackend, VideoInfo};
+use crate::{Error, Result, Rgb};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// GIF colors are stored in palettes of at most 256 entries.
+const MAX_PALETTE_SIZE: usize = 256;
+
+/// Animated GIF output. Requires the `gif` feature.
+///
+/// Frames are written as they are added. The global palette holds the colors of the first
+/// frame, if it has 256 colors or fewer, which suits the few distinct colors of most grids.
+/// Later frames with colors missing from the global palette are written with a local palette
+/// of their own, quantized if the frame has more than 256 colors.
+///
+/// Frame delays are derived from [EncoderBuilder::fps](crate::EncoderBuilder::fps) or
+/// [EncoderBuilder::frame_rate](crate::EncoderBuilder::frame_rate), or taken from
+/// [Encoder::add_frame_with_duration](crate::Encoder::add_frame_with_duration). GIF delays are
+/// stored in hundredths of a second, so per-frame delays are rounded such that the total duration
+/// does not drift.
+pub struct Gif {
+    filepath: PathBuf,
+    file: Option<fs::File>,
+    repeat: Option<u16>,
+    info: Option<VideoInfo>,
+    /// Created with the global palette when the first frame is written.
+    encoder: Option<gif::Encoder<BufWriter<fs::File>>>,
+    palette: Palette,
+    timeline: Timeline,
+}
+
+/// Colors of a GIF palette, and the index of each.
+#[derive(Default)]
+struct Palette {
+    colors: Vec<u8>,
+    indices: HashMap<Rgb, u8>,
+}
+
+impl Palette {
+    /// Returns the palette of every color in `rgb`, in order of appearance, with each pixel
+    /// mapped to it. Returns `None` if there are more than 256 colors.
+    fn from_pixels(rgb: &[u8]) -> Option<(Self, Vec<u8>)> {
+        let mut palette = Palette::default();
+        let mut indices = Vec::with_capacity(rgb.len() / 3);
+        for pixel in rgb.chunks_exact(3) {
+            let color: Rgb = (pixel[0], pixel[1], pixel[2]);
+            let index = match palette.indices.get(&color) {
+                Some(&index) => index,
+                None => {
+                    let index = palette.indices.len();
+                    if index >= MAX_PALETTE_SIZE {
+                        return None;
+                    }
+                    palette.indices.insert(color, index as u8);
+                    palette.colors.extend_from_slice(pixel);
+                    index as u8
+                }
+            };
+            indices.push(index);
+        }
+        Some((palette, indices))
+    }
+
+    /// Maps each pixel to this palette. Returns `None` if a color is missing.
+    fn index_pixels(&self, rgb: &[u8]) -> Option<Vec<u8>> {
+        rgb.chunks_exact(3)
+            .map(|pixel| self.indices.get(&(pixel[0], pixel[1], pixel[2])).copied())
+            .collect()
+    }
+}
+
+impl Gif {
+    /// Returns a new GIF backend writing to `filepath`. Warns if it does not end with the extension `.gif`.
+    ///
+    /// The output file is created when the [Encoder](crate::Encoder) is built,
+    /// and an error is returned if it already exists.
+    pub fn new<P: AsRef<Path>>(filepath: P) -> Self {
+        let filepath = filepath.as_ref().to_owned();
+
+        if filepath.extension() != Some("gif".as_ref()) {
+            log::warn!("video filename extension is not `.gif`");
+        }
+
+        Gif {
+            filepath,
+            file: None,
+            repeat: None,
+            info: None,
+            encoder: None,
+            palette: Palette::default(),
+            // GIF delays are in hundredths of a second
+            timeline: Timeline::new(FrameRate { num: 100, den: 1 }),
+        }
+    }
+
+    /// Sets how many times the animation repeats after it first plays. `None` loops forever.
+    ///
+    /// Default: `None`
+    pub fn repeat(mut self, repeat: Option<u16>) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Writes the header, with the colors of the first frame as the global palette.
+    fn start(&mut self, width: u16, height: u16, first_frame: &[u8]) -> Result<()> {
+        let file = self.file.take().ok_or_else(output_lost)?;
+        self.palette = Palette::from_pixels(first_frame)
+            .map(|(palette, _)| palette)
+            .unwrap_or_default();
+
+        let mut encoder =
+            gif::Encoder::new(BufWriter::new(file), width, height, &self.palette.colors)?;
+        encoder.set_repeat(match self.repeat {
+            Some(count) => gif::Repeat::Finite(count),
+            None => gif::Repeat::Infinite,
+        })?;
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+}
+
+impl VideoBackend for Gif {
+    fn open(&mut self) -> Result<()> {
+        self.file = Some(super::create_file(&self.filepath)?);
+        Ok(())
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        let max = u16::MAX as usize;
+        if info.width > max || info.height > max {
+            return Err(Error::UnsupportedFrameDimensions((info.width, info.height)));
+        }
+        if info.fps.as_f64() > 50.0 {
+            log::warn!("many GIF viewers slow down animations above 50 fps");
+        }
+
+        self.info = Some(*info);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, rgb: &[u8], duration: Duration) -> Result<()> {
+        let info = self.info.as_ref().unwrap();
+        let (width, height) = (info.width as u16, info.height as u16);
+
+        if self.encoder.is_none() {
+            self.start(width, height, rgb)?;
+        }
+        let mut frame = match self.palette.index_pixels(rgb) {
+            Some(indices) => gif::Frame::from_indexed_pixels(width, height, &indices, None),
+            None => local_palette_frame(width, height, rgb),
+        };
+        frame.delay = self.timeline.advance(duration).min(u16::MAX as u64) as u16;
+        self.encoder.as_mut().unwrap().write_frame(&frame)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let encoder = self.encoder.take().ok_or_else(output_lost)?;
+        encoder.into_inner()?.flush()?;
+
+        log::debug!("video output written: {}", &self.filepath.display());
+        Ok(())
+    }
+}
+
+/// Error returned after the header could not be written.
+fn output_lost() -> io::Error {
+    io::Error::other("GIF output was lost after an earlier write error")
+}
+
+/// Returns a frame with a local palette of its own colors, quantized if there are more than 256.
+fn local_palette_frame(width: u16, height: u16, rgb: &[u8]) -> gif::Frame<'static> {
+    log::debug!("GIF frame has colors missing from the global palette, using a local palette");
+    match Palette::from_pixels(rgb) {
+        Some((palette, indices)) => {
+            gif::Frame::from_palette_pixels(width, height, &indices, &palette.colors, None)
+        }
+        None => gif::Frame::from_rgb_speed(width, height, rgb, 10),
+    }
+}
@@ -0,0 +1,280 @@
+use super::{FrameFormat, H264Settings, VideoBackend, VideoInfo};
+use crate::{Error, RateControl, Result};
+use openh264::{
+    encoder::{Encoder as OpenH264Encoder, EncoderConfig, RateControlMode},
+    formats::YUVSource,
+};
+use openh264_sys2::{
+    SEncParamExt, ENCODER_OPTION_IDR_INTERVAL, ENCODER_OPTION_SVC_ENCODE_PARAM_EXT,
+};
+use std::{
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    ptr::addr_of_mut,
+    time::Duration,
+};
+
+/// OpenH264 encoder shared by the H.264 backends.
+pub(super) struct H264Encoder {
+    encoder: OpenH264Encoder,
+    width: usize,
+    height: usize,
+}
+
+impl H264Encoder {
+    /// Checks the OpenH264 frame requirements without creating an encoder.
+    pub(super) fn validate(info: &VideoInfo) -> Result<()> {
+        let (width, height) = (info.width, info.height);
+
+        if width * height > crate::error::OPENH264_MAX_SIZE {
+            return Err(Error::OversizedFrame((width, height)));
+        };
+        if width == 0 || height == 0 || width % 2 == 1 || height % 2 == 1 {
+            return Err(Error::InvalidFrameDimensions((width, height)));
+        }
+        Ok(())
+    }
+
+    /// Validates OpenH264 frame requirements and creates the encoder.
+    pub(super) fn new(info: &VideoInfo) -> Result<Self> {
+        Self::validate(info)?;
+        let (width, height) = (info.width, info.height);
+
+        let settings = &info.h264;
+        let mode = match settings.rate_control {
+            RateControl::Quality => RateControlMode::Quality,
+            RateControl::Bitrate => RateControlMode::Bitrate,
+            RateControl::BufferBased => RateControlMode::Bufferbased,
+            RateControl::ConstantQp(_) => RateControlMode::Off,
+        };
+        // Rate control budgets bits per second of video, so it needs the actual frame rate.
+        // Skipped frames produce no sample, which would drop their duration from the video.
+        let mut config = EncoderConfig::new(width as u32, height as u32)
+            .rate_control_mode(mode)
+            .max_frame_rate(info.fps.as_f64() as f32)
+            .enable_skip_frame(false);
+        if let Some(bps) = settings.bitrate {
+            config = config.set_bitrate_bps(bps);
+        }
+
+        let mut encoder = OpenH264Encoder::with_config(config)?;
+        set_quantization(&mut encoder, settings)?;
+        if let Some(interval) = settings.keyframe_interval {
+            let mut interval = interval.min(i32::MAX as u32) as i32;
+            // SAFETY: OpenH264 reads this option as an `int`.
+            check_option(unsafe {
+                encoder
+                    .raw_api()
+                    .set_option(ENCODER_OPTION_IDR_INTERVAL, addr_of_mut!(interval).cast())
+            })?;
+        }
+
+        Ok(H264Encoder {
+            encoder,
+            width,
+            height,
+        })
+    }
+
+    /// Encodes a planar YUV 4:2:0 frame and appends the Annex-B NAL units to `dst`.
+    pub(super) fn encode(&mut self, yuv: &[u8], dst: &mut Vec<u8>) -> Result<()> {
+        let yuv = Yuv420 {
+            data: yuv,
+            width: self.width,
+            height: self.height,
+        };
+
+        // Encode YUV into H.264.
+        let bitstream = self.encoder.encode(&yuv)?;
+        bitstream.write_vec(dst);
+        Ok(())
+    }
+
+    /// Encodes the next frame as an IDR keyframe.
+    pub(super) fn force_keyframe(&mut self) -> Result<()> {
+        // SAFETY: The encoder is initialized.
+        check_option(unsafe { self.encoder.raw_api().force_intra_frame(true) })
+    }
+}
+
+/// A borrowed planar YUV 4:2:0 frame, as rasterized for [FrameFormat::Yuv420].
+struct Yuv420<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl YUVSource for Yuv420<'_> {
+    fn width(&self) -> i32 {
+        self.width as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.height as i32
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.data[..self.width * self.height]
+    }
+
+    fn u(&self) -> &[u8] {
+        let luma = self.width * self.height;
+        &self.data[luma..luma + luma / 4]
+    }
+
+    fn v(&self) -> &[u8] {
+        let luma = self.width * self.height;
+        &self.data[luma + luma / 4..]
+    }
+
+    fn y_stride(&self) -> i32 {
+        self.width as i32
+    }
+
+    fn u_stride(&self) -> i32 {
+        (self.width / 2) as i32
+    }
+
+    fn v_stride(&self) -> i32 {
+        (self.width / 2) as i32
+    }
+}
+
+/// Applies the QP settings, which are not part of [EncoderConfig].
+fn set_quantization(encoder: &mut OpenH264Encoder, settings: &H264Settings) -> Result<()> {
+    if settings.qp_range.is_none() && !matches!(settings.rate_control, RateControl::ConstantQp(_)) {
+        return Ok(());
+    }
+
+    let mut params = SEncParamExt::default();
+    // SAFETY: `SEncParamExt` is the type OpenH264 expects for this option, and it is only copied.
+    unsafe {
+        let raw_api = encoder.raw_api();
+        check_option(raw_api.get_option(
+            ENCODER_OPTION_SVC_ENCODE_PARAM_EXT,
+            addr_of_mut!(params).cast(),
+        ))?;
+
+        if let Some((min, max)) = settings.qp_range {
+            params.iMinQp = min as i32;
+            params.iMaxQp = max as i32;
+        }
+        if let RateControl::ConstantQp(qp) = settings.rate_control {
+            params.sSpatialLayers[0].iDLayerQp = qp as i32;
+            // Adaptive quantization would vary the QP between macroblocks.
+            params.bEnableAdaptiveQuant = false;
+        }
+
+        // OpenH264 only reads the QP range when the encoder is reset, which happens when
+        // adaptive quantization is toggled. Apply the parameters with it inverted, then as set.
+        for _ in 0..2 {
+            params.bEnableAdaptiveQuant = !params.bEnableAdaptiveQuant;
+            check_option(raw_api.set_option(
+                ENCODER_OPTION_SVC_ENCODE_PARAM_EXT,
+                addr_of_mut!(params).cast(),
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Converts an OpenH264 status code into a Result.
+fn check_option(status: i32) -> Result<()> {
+    match status {
+        0 => Ok(()),
+        _ => Err(Error::Openh264Error(openh264::Error::msg(&format!(
+            "encoder request failed (status {status})"
+        )))),
+    }
+}
+
+enum Output {
+    Path(PathBuf),
+    Writer(Box<dyn Write + Send>),
+}
+
+/// Raw H.264 Annex-B elementary stream output, without an MP4 container.
+///
+/// Suitable for RTP packetizers, hardware decoders and external muxers.
+/// Frames are written as soon as they are encoded. The stream has no timestamps,
+/// so frame durations are not stored.
+pub struct H264 {
+    output: Output,
+    encoder: Option<H264Encoder>,
+    buffer: Vec<u8>,
+}
+
+impl H264 {
+    /// Returns a new H.264 backend writing to `filepath`. Warns if it does not end with the extension `.h264`.
+    ///
+    /// The output file is created when the [Encoder](crate::Encoder) is built,
+    /// and an error is returned if it already exists.
+    pub fn new<P: AsRef<Path>>(filepath: P) -> Self {
+        let filepath = filepath.as_ref().to_owned();
+
+        if filepath.extension() != Some("h264".as_ref()) {
+            log::warn!("video filename extension is not `.h264`");
+        }
+
+        H264 {
+            output: Output::Path(filepath),
+            encoder: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Returns a new H.264 backend writing to a caller-supplied writer.
+    pub fn from_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        H264 {
+            output: Output::Writer(Box::new(writer)),
+            encoder: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match &mut self.output {
+            Output::Writer(writer) => writer,
+            Output::Path(_) => unreachable!("output file is created when the encoder is built"),
+        }
+    }
+}
+
+impl VideoBackend for H264 {
+    fn open(&mut self) -> Result<()> {
+        if let Output::Path(filepath) = &self.output {
+            let file = super::create_file(filepath)?;
+            self.output = Output::Writer(Box::new(BufWriter::new(file)));
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        self.encoder = Some(H264Encoder::new(info)?);
+        Ok(())
+    }
+
+    fn frame_format(&self) -> FrameFormat {
+        FrameFormat::Yuv420
+    }
+
+    fn write_frame(&mut self, yuv: &[u8], _duration: Duration) -> Result<()> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        self.encoder.as_mut().unwrap().encode(yuv, &mut buffer)?;
+        self.writer().write_all(&buffer)?;
+        self.buffer = buffer;
+        Ok(())
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.encoder.as_mut().unwrap().force_keyframe()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer().flush()?;
+
+        log::debug!("H.264 stream written");
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use super::{h264::H264Encoder, FrameFormat, FrameRate, Timeline, VideoBackend, VideoInfo};
+use crate::Result;
+use std::{
+    fs,
+    io::{self, BufWriter, Cursor, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+mod muxer;
+mod segments;
+
+/// A seekable writer. MP4 headers are written after the media data they describe.
+trait WriteSeek: Write + Seek + Send {}
+impl<W: Write + Seek + Send> WriteSeek for W {}
+
+/// MP4 output destination.
+enum Output {
+    File(BufWriter<fs::File>),
+    Writer(Box<dyn WriteSeek>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::File(w) => w.write(buf),
+            Output::Writer(w) => w.write(buf),
+            Output::Memory(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::File(w) => w.flush(),
+            Output::Writer(w) => w.flush(),
+            Output::Memory(w) => w.flush(),
+        }
+    }
+}
+
+impl Seek for Output {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Output::File(w) => w.seek(pos),
+            Output::Writer(w) => w.seek(pos),
+            Output::Memory(w) => w.seek(pos),
+        }
+    }
+}
+
+/// H.264 MP4 output using OpenH264 and minimp4. This is the default [VideoBackend].
+///
+/// Frames are muxed as they are encoded, so memory use does not grow with video length.
+/// For offline renders, [parallel_segments](Mp4::parallel_segments) encodes runs of frames on
+/// separate threads instead.
+pub struct Mp4 {
+    filepath: Option<PathBuf>,
+    output: Option<Output>,
+    encoder: Option<H264Encoder>,
+    muxer: Option<muxer::Muxer>,
+    buffer: Vec<u8>,
+    timeline: Timeline,
+    fragmented: bool,
+    segment_len: Option<usize>,
+    segments: Option<segments::Segments>,
+    in_memory: bool,
+}
+
+impl Mp4 {
+    /// Returns a new MP4 backend writing to `filepath`. Warns if it does not end with the extension `.mp4`.
+    ///
+    /// The output file is created when the [Encoder](crate::Encoder) is built,
+    /// and an error is returned if it already exists.
+    pub fn new<P: AsRef<Path>>(filepath: P) -> Self {
+        let filepath = filepath.as_ref().to_owned();
+
+        if filepath.extension() != Some("mp4".as_ref()) {
+            log::warn!("video filename extension is not `.mp4`");
+        }
+
+        Mp4 {
+            filepath: Some(filepath),
+            ..Self::with_output(None)
+        }
+    }
+
+    /// Returns a new MP4 backend writing to any seekable writer, e.g. a [File](std::fs::File).
+    pub fn from_writer<W: Write + Seek + Send + 'static>(writer: W) -> Self {
+        Self::with_output(Some(Output::Writer(Box::new(writer))))
+    }
+
+    /// Returns a new MP4 backend that keeps the video in memory.
+    ///
+    /// The MP4 bytes are returned by [Encoder::close_to_vec](crate::Encoder::close_to_vec).
+    pub fn in_memory() -> Self {
+        Mp4 {
+            in_memory: true,
+            ..Self::with_output(Some(Output::Memory(Cursor::new(Vec::new()))))
+        }
+    }
+
+    fn with_output(output: Option<Output>) -> Self {
+        Mp4 {
+            filepath: None,
+            output,
+            encoder: None,
+            muxer: None,
+            buffer: Vec::new(),
+            timeline: Timeline::new(FrameRate {
+                num: muxer::TIMESCALE,
+                den: 1,
+            }),
+            fragmented: false,
+            segment_len: None,
+            segments: None,
+            in_memory: false,
+        }
+    }
+
+    /// Sets whether to write a fragmented MP4, with a `moof` and `mdat` box for every frame.
+    ///
+    /// Fragments are flushed to the output as soon as each frame is added. If the program crashes
+    /// or is killed before [Encoder::close](crate::Encoder::close), the output is still playable
+    /// up to the last added frame. Fragmented MP4s are slightly larger, and some older players
+    /// do not support them.
+    ///
+    /// Default: `false`
+    pub fn fragmented(mut self, fragmented: bool) -> Self {
+        self.fragmented = fragmented;
+        self
+    }
+
+    /// Splits the video into segments of `segment_len` frames, and encodes each segment with its
+    /// own OpenH264 encoder on its own thread. Encoded segments are muxed in order into a single MP4.
+    ///
+    /// Every segment starts with an IDR keyframe, and at most one segment per available CPU core
+    /// is encoded at a time. This makes encoding scale with core count when frames are added
+    /// faster than a single encoder can keep up, e.g. when re-rendering frames that were computed
+    /// ahead of time.
+    ///
+    /// The frames of each segment are held in memory until they are encoded, so memory use grows
+    /// with `segment_len`. Segments are only written to the output once they are encoded, which
+    /// delays the fragments of a [fragmented](Mp4::fragmented) MP4. Shorter segments add more
+    /// keyframes, which makes the video larger.
+    ///
+    /// Default: `None`, which encodes every frame on the calling thread
+    pub fn parallel_segments(mut self, segment_len: Option<usize>) -> Self {
+        self.segment_len = segment_len;
+        self
+    }
+}
+
+impl VideoBackend for Mp4 {
+    fn open(&mut self) -> Result<()> {
+        if let Some(filepath) = &self.filepath {
+            let file = BufWriter::new(super::create_file(filepath)?);
+            self.output = Some(Output::File(file));
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        // Segment workers create their own encoders
+        let encoder = match self.segment_len {
+            Some(_) => {
+                H264Encoder::validate(info)?;
+                None
+            }
+            None => Some(H264Encoder::new(info)?),
+        };
+        // The muxer takes ownership of the output, which is lost if writing the header fails
+        let output = self
+            .output
+            .take()
+            .ok_or_else(|| io::Error::other("MP4 output was lost after an earlier write error"))?;
+        self.muxer = Some(muxer::Muxer::new(output, info, self.fragmented)?);
+        match self.segment_len {
+            Some(len) => {
+                let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+                self.segments = Some(segments::Segments::new(info, len, threads));
+            }
+            None => self.encoder = encoder,
+        }
+        Ok(())
+    }
+
+    fn frame_format(&self) -> FrameFormat {
+        FrameFormat::Yuv420
+    }
+
+    fn write_frame(&mut self, yuv: &[u8], duration: Duration) -> Result<()> {
+        let duration = self.timeline.advance(duration).min(u32::MAX as u64) as u32;
+        let muxer = self.muxer.as_mut().unwrap();
+        if let Some(segments) = &mut self.segments {
+            return segments.write(yuv, duration, muxer);
+        }
+
+        self.buffer.clear();
+        self.encoder
+            .as_mut()
+            .unwrap()
+            .encode(yuv, &mut self.buffer)?;
+        muxer.write(&self.buffer, duration)
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        match &mut self.segments {
+            Some(segments) => segments.force_keyframe(),
+            None => self.encoder.as_mut().unwrap().force_keyframe(),
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(segments) = &mut self.segments {
+            segments.finish(self.muxer.as_mut().unwrap())?;
+        }
+        self.output = Some(self.muxer.take().unwrap().close()?);
+
+        if let Some(filepath) = &self.filepath {
+            log::debug!("video output written: {}", filepath.display());
+        }
+        Ok(())
+    }
+
+    fn writes_to_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    fn take_bytes(&mut self) -> Option<Vec<u8>> {
+        match &mut self.output {
+            Some(Output::Memory(cursor)) => Some(std::mem::take(cursor.get_mut())),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::Output;
+use crate::{backend::VideoInfo, Error, Result};
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    os::raw::{c_int, c_uint, c_void},
+    ptr,
+};
+
+mod minimp4;
+
+/// minimp4 `MP4E_STATUS_FILE_WRITE_ERROR`
+const WRITE_ERROR_STATUS: c_int = -3;
+
+/// Track timescale used by minimp4, in units per second.
+pub const TIMESCALE: u32 = 90000;
+
+/// Destination passed through minimp4 as the write callback token.
+struct Sink {
+    output: Output,
+    error: Option<io::Error>,
+}
+
+/// Receives MP4 output from minimp4. Returns 0 on success.
+unsafe extern "C" fn write_callback(
+    offset: i64,
+    buffer: *const c_void,
+    size: usize,
+    token: *mut c_void,
+) -> c_int {
+    // SAFETY: `token` is the boxed `Sink` owned by the `Muxer`, which outlives the minimp4 muxer,
+    // and minimp4 passes a valid buffer of `size` bytes.
+    let sink = &mut *(token as *mut Sink);
+    let data = std::slice::from_raw_parts(buffer as *const u8, size);
+
+    let result = sink
+        .output
+        .seek(SeekFrom::Start(offset as u64))
+        .and_then(|_| sink.output.write_all(data));
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            sink.error.get_or_insert(e);
+            WRITE_ERROR_STATUS
+        }
+    }
+}
+
+/// Muxes H.264 frames into an MP4 as they are encoded.
+///
+/// Media data is written immediately. Only the sample index is kept in memory until [close](Muxer::close),
+/// unless the MP4 is fragmented.
+pub struct Muxer {
+    muxer: *mut minimp4::mp4_muxer_t,
+    sink: Box<Sink>,
+    fragmented: bool,
+}
+
+impl Muxer {
+    /// Writes the MP4 header to `output`. Fragmented MP4s write a fragment per frame.
+    pub fn new(output: Output, info: &VideoInfo, fragmented: bool) -> Result<Self> {
+        let mut muxer = Muxer {
+            muxer: ptr::null_mut(),
+            sink: Box::new(Sink {
+                output,
+                error: None,
+            }),
+            fragmented,
+        };
+
+        // SAFETY: The sink is boxed, so its address remains valid while the muxer is open.
+        let status = unsafe {
+            minimp4::mp4_mux_open(
+                &mut muxer.muxer,
+                Some(write_callback),
+                muxer.sink.as_mut() as *mut Sink as *mut c_void,
+                info.width as i32,
+                info.height as i32,
+                fragmented as c_int,
+            )
+        };
+        muxer.check(status)?;
+        Ok(muxer)
+    }
+
+    /// Writes the Annex-B NAL units of a single frame. `duration` is in units of 1/[TIMESCALE] s.
+    pub fn write(&mut self, nal_units: &[u8], duration: u32) -> Result<()> {
+        // SAFETY: The muxer is open, and minimp4 only reads from the buffer.
+        let status = unsafe {
+            minimp4::mp4_mux_write(
+                self.muxer,
+                nal_units.as_ptr() as *mut u8,
+                nal_units.len() as isize,
+                duration as c_uint,
+            )
+        };
+        self.check(status)?;
+
+        // Fragments are only useful after a crash if they reached the output
+        if self.fragmented {
+            self.sink.output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the MP4 index and returns the output.
+    pub fn close(mut self) -> Result<Output> {
+        let status = self.close_muxer();
+        self.check(status)?;
+        self.sink.output.flush()?;
+
+        // Muxer implements Drop, so the output is swapped out rather than moved
+        let empty = Output::Memory(io::Cursor::new(Vec::new()));
+        Ok(std::mem::replace(&mut self.sink.output, empty))
+    }
+
+    fn close_muxer(&mut self) -> c_int {
+        if self.muxer.is_null() {
+            return 0;
+        }
+
+        // SAFETY: The muxer is open and is not used after being freed.
+        let status = unsafe { minimp4::mp4_mux_close(self.muxer) };
+        self.muxer = ptr::null_mut();
+        status
+    }
+
+    /// Converts a minimp4 status into a Result. I/O errors are more specific than the status they cause.
+    fn check(&mut self, status: c_int) -> Result<()> {
+        if let Some(e) = self.sink.error.take() {
+            return Err(Error::IoError(e));
+        }
+        if status != 0 {
+            return Err(Error::MuxerError(status));
+        }
+        Ok(())
+    }
+}
+
+// SAFETY: The minimp4 muxer is owned by this struct and only accessed through `&mut self`.
+// minimp4 keeps no thread-local state, and the sink it writes to is `Send`.
+unsafe impl Send for Muxer {}
+
+impl Drop for Muxer {
+    fn drop(&mut self) {
+        // Frees the minimp4 muxer. The index is written, so frames muxed so far remain playable.
+        self.close_muxer();
+    }
+}
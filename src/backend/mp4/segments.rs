@@ -0,0 +1,192 @@
+use super::muxer::Muxer;
+use crate::{
+    backend::{h264::H264Encoder, VideoInfo},
+    Error, Result,
+};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+/// Encodes consecutive runs of frames on worker threads, each with its own OpenH264 encoder,
+/// and muxes the results in order.
+///
+/// A new encoder starts with an IDR frame, so every segment can be decoded independently of the
+/// segments before it.
+///
+/// After an error, every later call fails, so that a missing segment is never muxed as a success.
+pub struct Segments {
+    info: VideoInfo,
+    len: usize,
+    threads: usize,
+    /// Segment receiving frames.
+    current: Option<Segment>,
+    /// Segments that have received all of their frames, oldest first.
+    encoding: VecDeque<Segment>,
+    failed: bool,
+}
+
+struct Segment {
+    requests: SyncSender<Request>,
+    frames: usize,
+    /// `None` once the worker has been joined after it stopped.
+    worker: Option<Worker>,
+}
+
+/// Frames that may wait for a segment worker, so that a slow encoder does not buffer a whole
+/// segment of raw frames.
+const QUEUE_LEN: usize = 2;
+
+type Worker = JoinHandle<Result<Encoded>>;
+
+enum Request {
+    /// YUV frame and its duration in units of 1/[TIMESCALE](super::muxer::TIMESCALE) s.
+    Frame(Vec<u8>, u32),
+    ForceKeyframe,
+}
+
+/// Annex-B output of a segment, with the size and duration of each sample.
+#[derive(Default)]
+struct Encoded {
+    data: Vec<u8>,
+    samples: Vec<(usize, u32)>,
+}
+
+impl Segments {
+    /// Encodes segments of `len` frames, with at most `threads` segments encoding at once.
+    pub fn new(info: &VideoInfo, len: usize, threads: usize) -> Self {
+        Segments {
+            info: *info,
+            len: len.max(1),
+            threads: threads.max(1),
+            current: None,
+            encoding: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Queues a frame, starting a new segment if the current one is full.
+    ///
+    /// Waits for the oldest segment to be encoded and muxed if too many are encoding.
+    pub fn write(&mut self, yuv: &[u8], duration: u32, muxer: &mut Muxer) -> Result<()> {
+        self.check(|segments| segments.queue(yuv, duration, muxer))
+    }
+
+    /// Encodes the next frame of the current segment as an IDR keyframe.
+    ///
+    /// The first frame of a new segment is always a keyframe.
+    pub fn force_keyframe(&mut self) -> Result<()> {
+        self.check(|segments| match segments.current.as_mut() {
+            Some(segment) if segment.frames < segments.len => segment.send(Request::ForceKeyframe),
+            _ => Ok(()),
+        })
+    }
+
+    /// Waits for all segments to be encoded, and muxes them in order.
+    pub fn finish(&mut self, muxer: &mut Muxer) -> Result<()> {
+        self.check(|segments| {
+            segments.encoding.extend(segments.current.take());
+            while let Some(segment) = segments.encoding.pop_front() {
+                mux(segment, muxer)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs `f` unless an earlier call failed, and records whether it fails.
+    fn check(&mut self, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        if self.failed {
+            return Err(stopped());
+        }
+        let result = f(self);
+        self.failed = result.is_err();
+        result
+    }
+
+    fn queue(&mut self, yuv: &[u8], duration: u32, muxer: &mut Muxer) -> Result<()> {
+        if self.current.as_ref().is_some_and(|s| s.frames >= self.len) {
+            self.encoding.extend(self.current.take());
+        }
+        if self.current.is_none() {
+            while self.encoding.len() >= self.threads {
+                mux(self.encoding.pop_front().unwrap(), muxer)?;
+            }
+            self.current = Some(Segment::spawn(self.info));
+        }
+
+        let segment = self.current.as_mut().unwrap();
+        segment.frames += 1;
+        segment.send(Request::Frame(yuv.to_vec(), duration))
+    }
+}
+
+impl Segment {
+    fn spawn(info: VideoInfo) -> Self {
+        let (requests, queue) = mpsc::sync_channel(QUEUE_LEN);
+        Segment {
+            requests,
+            frames: 0,
+            // Each encoder is created by its worker, so that setup also runs in parallel
+            worker: Some(thread::spawn(move || encode(&info, queue))),
+        }
+    }
+
+    /// Sends a request to the worker. If the worker has stopped, returns its error instead.
+    fn send(&mut self, request: Request) -> Result<()> {
+        if self.requests.send(request).is_err() {
+            // The worker only stops early with an error
+            self.worker.take().map_or_else(|| Err(stopped()), join)?;
+            return Err(stopped());
+        }
+        Ok(())
+    }
+
+    /// Closes the queue and waits for the worker to encode the remaining frames.
+    fn finish(self) -> Result<Encoded> {
+        drop(self.requests);
+        self.worker.map_or_else(|| Err(stopped()), join)
+    }
+}
+
+/// Error returned after a segment has failed.
+fn stopped() -> Error {
+    Error::IoError(io::Error::other(
+        "segment encoder stopped after an earlier error",
+    ))
+}
+
+fn join(worker: Worker) -> Result<Encoded> {
+    match worker.join() {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+/// Encodes frames until the queue is closed.
+fn encode(info: &VideoInfo, queue: Receiver<Request>) -> Result<Encoded> {
+    let mut encoder = H264Encoder::new(info)?;
+    let mut encoded = Encoded::default();
+    while let Ok(request) = queue.recv() {
+        match request {
+            Request::Frame(yuv, duration) => {
+                let start = encoded.data.len();
+                encoder.encode(&yuv, &mut encoded.data)?;
+                encoded.samples.push((encoded.data.len() - start, duration));
+            }
+            Request::ForceKeyframe => encoder.force_keyframe()?,
+        }
+    }
+    Ok(encoded)
+}
+
+fn mux(segment: Segment, muxer: &mut Muxer) -> Result<()> {
+    let encoded = segment.finish()?;
+    let mut start = 0;
+    for (size, duration) in encoded.samples {
+        muxer.write(&encoded.data[start..start + size], duration)?;
+        start += size;
+    }
+    Ok(())
+}
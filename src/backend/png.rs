@@ -0,0 +1,84 @@
+use super::{VideoBackend, VideoInfo};
+use crate::Result;
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Lossless PNG image sequence output. Requires the `png` feature.
+///
+/// Each frame is written to the output directory as a numbered PNG file:
+/// `frame_00001.png`, `frame_00002.png`, etc. Pixels match the rasterized grid exactly.
+/// Frame durations are not stored.
+pub struct PngSequence {
+    directory: PathBuf,
+    info: Option<VideoInfo>,
+    frame_count: usize,
+}
+
+impl PngSequence {
+    /// Returns a new PNG sequence backend writing into `directory`.
+    ///
+    /// The directory is created when the [Encoder](crate::Encoder) is built if it does not
+    /// already exist. Existing frame files are never overwritten.
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        PngSequence {
+            directory: directory.as_ref().to_owned(),
+            info: None,
+            frame_count: 0,
+        }
+    }
+
+    fn frame_path(&self, frame_number: usize) -> PathBuf {
+        self.directory.join(format!("frame_{frame_number:05}.png"))
+    }
+}
+
+/// Encodes a packed RGB image as PNG.
+pub(crate) fn write_png<W: Write>(w: W, width: usize, height: usize, rgb: &[u8]) -> Result<()> {
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+impl VideoBackend for PngSequence {
+    fn open(&mut self) -> Result<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        // Fail early rather than on the first frame
+        super::ensure_new_file(&self.frame_path(1))
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        self.info = Some(*info);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, rgb: &[u8], _duration: Duration) -> Result<()> {
+        let info = self.info.as_ref().unwrap();
+        let file = super::create_file(&self.frame_path(self.frame_count + 1))?;
+
+        let mut w = BufWriter::new(file);
+        write_png(&mut w, info.width, info.height, rgb)?;
+        w.flush()?;
+
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        log::debug!(
+            "{} frames written to: {}",
+            self.frame_count,
+            &self.directory.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use super::{FrameFormat, Timeline, VideoBackend, VideoInfo};
+use crate::{Error, Result};
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Uncompressed YUV4MPEG2 output, for piping into external encoders or keeping a lossless master.
+///
+/// Frames are converted to 4:2:0 YUV with the same conversion used by the [Mp4](super::Mp4) backend,
+/// so frame width and height must both be multiples of 2.
+///
+/// YUV4MPEG2 has a constant frame rate. Frames added with
+/// [Encoder::add_frame_with_duration](crate::Encoder::add_frame_with_duration) are repeated to
+/// fill their duration, rounded to whole frame intervals.
+pub struct Y4m {
+    filepath: PathBuf,
+    writer: Option<BufWriter<fs::File>>,
+    timeline: Option<Timeline>,
+}
+
+impl Y4m {
+    /// Returns a new Y4M backend writing to `filepath`. Warns if it does not end with the extension `.y4m`.
+    ///
+    /// The output file is created when the [Encoder](crate::Encoder) is built,
+    /// and an error is returned if it already exists.
+    pub fn new<P: AsRef<Path>>(filepath: P) -> Self {
+        let filepath = filepath.as_ref().to_owned();
+
+        if filepath.extension() != Some("y4m".as_ref()) {
+            log::warn!("video filename extension is not `.y4m`");
+        }
+
+        Y4m {
+            filepath,
+            writer: None,
+            timeline: None,
+        }
+    }
+}
+
+impl VideoBackend for Y4m {
+    fn open(&mut self) -> Result<()> {
+        self.writer = Some(BufWriter::new(super::create_file(&self.filepath)?));
+        Ok(())
+    }
+
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        let (width, height) = (info.width, info.height);
+        if width == 0 || height == 0 || width % 2 == 1 || height % 2 == 1 {
+            return Err(Error::InvalidFrameDimensions((width, height)));
+        }
+
+        // Limited range BT.601, with chroma averaged over each 2x2 block
+        let writer = self.writer.as_mut().unwrap();
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{width} H{height} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+            info.fps.num, info.fps.den
+        )?;
+
+        self.timeline = Some(Timeline::new(info.fps));
+        Ok(())
+    }
+
+    fn frame_format(&self) -> FrameFormat {
+        FrameFormat::Yuv420
+    }
+
+    fn write_frame(&mut self, yuv: &[u8], duration: Duration) -> Result<()> {
+        // Frames are stored in the same planar layout they are rasterized in
+        let writer = self.writer.as_mut().unwrap();
+        for _ in 0..self.timeline.as_mut().unwrap().advance(duration) {
+            writer.write_all(b"FRAME\n")?;
+            writer.write_all(yuv)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.take().unwrap().flush()?;
+
+        log::debug!("video output written: {}", &self.filepath.display());
+        Ok(())
+    }
+}
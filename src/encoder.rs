@@ -1,15 +1,18 @@
-use crate::Error;
-use openh264::encoder::{Encoder as OpenH264Encoder, EncoderConfig};
-use std::{
-    fs, io,
-    path::{Path, PathBuf},
-};
+use crate::backend::{FrameFormat, FrameRate, H264Settings, Mp4, VideoBackend, VideoInfo};
+use crate::{grid::Oriented, Error, GridSource};
+use std::{io, marker::PhantomData, path::Path, time::Duration};
 
+mod color;
 mod image;
-mod muxer;
+mod snapshot;
+
+pub use color::ToRgb;
+pub use snapshot::{snapshot, Snapshot};
 
 const DEFAULT_FPS: u16 = 4;
 const DEFAULT_SCALE_MAX_SIZE: u16 = 720;
+const MAX_QP: u8 = 51;
+const NEAR_LOSSLESS_QP: u8 = 16;
 
 /// A tuple containing Red, Green and Blue color intensities.
 pub type Rgb = (u8, u8, u8);
@@ -18,6 +21,12 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// A function to map grid element type to [Rgb].
 pub type Converter<T> = dyn Fn(&T) -> Rgb;
+/// A [Converter] that can be moved to another thread, taken by [Encoder::with_backend_send].
+pub type SendConverter<T> = dyn Fn(&T) -> Rgb + Send;
+/// A [Converter] that can be called from several threads at once, taken by [Encoder::new_par]
+/// and [Encoder::with_backend_par]. Requires the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub type SyncConverter<T> = dyn Fn(&T) -> Rgb + Send + Sync;
 
 /// Options for upscaling the video.
 ///
@@ -45,29 +54,75 @@ pub enum Scaling {
     Stretch(u16, u16),
 }
 
-/// A video encoder wrapper. Converts grid to encoded video frames and writes output to a file.
+/// H.264 rate control modes, passed through to OpenH264.
+///
+/// Default for new [Encoders](Encoder): `Quality`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RateControl {
+    /// Favors visual quality over the target bitrate.
+    #[default]
+    Quality,
+    /// Keeps close to the target bitrate set with [EncoderBuilder::bitrate].
+    ///
+    /// Every frame is encoded, none are skipped, so bitrates too low for the content are exceeded.
+    Bitrate,
+    /// Adjusts quality from the encoder's buffer status, ignoring the target bitrate.
+    BufferBased,
+    /// Disables rate control and encodes every frame with the wrapped quantization parameter,
+    /// from 0 (best quality) to 51 (smallest size). Values above 51 are clamped.
+    ///
+    /// OpenH264 limits each encoded frame to roughly its uncompressed size, so very low values
+    /// can fail with an error on small, detailed frames.
+    ConstantQp(u8),
+}
+
+/// A video encoder wrapper. Converts grid to video frames and passes them to a [VideoBackend].
 ///
 /// ##### Defaults
 /// - Video frame rate is 4 [fps].
 /// - Black gridlines are inserted in between elements: [`Gridlines(0,0,0)`](Gridlines)
 /// - Video is scaled to 720x720 pixels: [`MaxSize(720, 720)`](Scaling)
 ///
+/// - Output is an H.264 MP4 file: [Mp4]
+///
+/// The converter type `C` is [Converter], [SendConverter] for a [SendEncoder], or
+/// `SyncConverter` for encoders that rasterize frames in parallel. The backend type `B` is
+/// `dyn VideoBackend`, or `dyn VideoBackend + Send` for a [SendEncoder].
+///
 /// [fps]: EncoderBuilder::fps
-
-pub struct Encoder<T> {
-    filepath: PathBuf,
+pub struct Encoder<T, C: ?Sized = Converter<T>, B: ?Sized = dyn VideoBackend> {
+    backend: Box<B>,
     width: Option<usize>,
     height: Option<usize>,
     scale: Scaling,
-    encoder: Option<OpenH264Encoder>,
-    buffer: Vec<u8>,
-    fps: u32,
+    fps: FrameRate,
     frame_count: usize,
+    interval_count: u64,
+    keyframe_requested: bool,
+    skip_identical_frames: bool,
+    pending_frame: Option<Frame>,
+    frame_format: FrameFormat,
+    frame_buffer: Vec<u8>,
+    palettes: Vec<image::YuvPalette>,
     gridlines: Gridlines,
-    converter: Box<Converter<T>>,
+    layout: Layout,
+    origin: Origin,
+    h264: H264Settings,
+    converter: Box<C>,
+    /// Grid element type, which only appears in the converter type.
+    element: PhantomData<fn(&T)>,
+}
+
+/// A rasterized frame waiting to be written to the [VideoBackend].
+struct Frame {
+    /// Pixels in the [FrameFormat] of the backend.
+    pixels: Vec<u8>,
+    duration: Duration,
+    keyframe: bool,
 }
 
 /// Options for showing or hiding gridlines. Gridlines are 2 pixels in width for all scaling options.
+#[derive(Clone, Copy, PartialEq)]
 pub enum Gridlines {
     /// Insert gridlines with the wrapped `(u8, u8, u8)` color in between elements for visual separation.
     Show(Rgb),
@@ -75,16 +130,68 @@ pub enum Gridlines {
     Hide,
 }
 
+/// Options for mapping the indices of a grid to the axes of the video.
+///
+/// Default for new [Encoders](Encoder): `ColumnMajor`.
+///
+/// For a [GridSource], the layout decides whether the first index of [GridSource::cell] selects
+/// a column or a row.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// The outer vector translates to the X-axis and the inner vectors to the Y-axis: `grid[x][y]`.
+    #[default]
+    ColumnMajor,
+    /// The outer vector translates to the Y-axis and the inner vectors to the X-axis: `grid[y][x]`,
+    /// as in most image and matrix libraries.
+    RowMajor,
+}
+
+/// Options for the corner of the video where the first column and row of a grid are shown.
+///
+/// Default for new [Encoders](Encoder): `BottomLeft`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// Columns run from left to right and rows from bottom to top, as on a plot.
+    #[default]
+    BottomLeft,
+    /// Columns run from left to right and rows from top to bottom, as in most image libraries.
+    TopLeft,
+    /// Columns run from right to left and rows from bottom to top.
+    BottomRight,
+    /// Columns run from right to left and rows from top to bottom.
+    TopRight,
+}
+
 /// EncoderBuilder allows for flexible customization of the video [Encoder].
-pub struct EncoderBuilder<T> {
-    filepath: PathBuf,
-    converter: Box<Converter<T>>,
+pub struct EncoderBuilder<T, C: ?Sized = Converter<T>, B: ?Sized = dyn VideoBackend> {
+    backend: Box<B>,
+    converter: Box<C>,
+    element: PhantomData<fn(&T)>,
     scale: Scaling,
-    fps: Option<u16>,
+    fps: Option<FrameRate>,
     gridlines: Option<Gridlines>,
+    layout: Layout,
+    origin: Origin,
+    h264: H264Settings,
+    skip_identical_frames: bool,
 }
 
-impl<T> EncoderBuilder<T> {
+impl<T, C: ?Sized, B: VideoBackend + ?Sized> EncoderBuilder<T, C, B> {
+    fn from_parts(backend: Box<B>, converter: Box<C>) -> Self {
+        EncoderBuilder {
+            backend,
+            converter,
+            element: PhantomData,
+            fps: None,
+            scale: Scaling::MaxSize(DEFAULT_SCALE_MAX_SIZE, DEFAULT_SCALE_MAX_SIZE),
+            gridlines: None,
+            layout: Layout::default(),
+            origin: Origin::default(),
+            h264: H264Settings::default(),
+            skip_identical_frames: false,
+        }
+    }
+
     /// Sets the video [Scaling] option.
     ///
     /// Default: `Scaling::MaxSize(720, 720)`
@@ -95,8 +202,19 @@ impl<T> EncoderBuilder<T> {
     /// Sets video frame rate.
     ///
     /// If unset, defaults to 4 fps.
-    pub fn fps(mut self, fps: u16) -> Self {
-        self.fps = if fps > 0 { Some(fps) } else { None };
+    pub fn fps(self, fps: u16) -> Self {
+        self.frame_rate(fps as u32, 1)
+    }
+    /// Sets a fractional video frame rate of `num / den` frames per second,
+    /// e.g. `frame_rate(30000, 1001)` for 29.97 fps or `frame_rate(1, 2)` for one frame every 2 seconds.
+    ///
+    /// If unset, defaults to 4 fps.
+    pub fn frame_rate(mut self, num: u32, den: u32) -> Self {
+        self.fps = if num > 0 && den > 0 {
+            Some(FrameRate { num, den })
+        } else {
+            None
+        };
         self
     }
     /// Indicates whether or not to show [Gridlines] and if so pick a color.
@@ -106,36 +224,115 @@ impl<T> EncoderBuilder<T> {
         self.gridlines = Some(gridlines);
         self
     }
+    /// Sets the grid [Layout], e.g. `Layout::RowMajor` for grids indexed as `grid[y][x]`.
+    ///
+    /// Grids are read in place, without being transposed or copied.
+    ///
+    /// Default: `Layout::ColumnMajor`
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+    /// Sets the [Origin] corner where the first column and row of a grid are shown,
+    /// e.g. `Origin::TopLeft` for grids stored from the top row down.
+    ///
+    /// Default: `Origin::BottomLeft`
+    pub fn origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
+    /// Sets whether identical consecutive frames are merged into a single frame lasting their
+    /// combined duration, so that repeated frames are not encoded again.
+    /// [Encoder::frame_count] still counts every added frame.
+    ///
+    /// Frames are compared after rasterization. Each frame is held back until a different frame
+    /// is added or the video is closed, so the latest frame is missing from the output until then.
+    ///
+    /// Default: `false`
+    pub fn skip_identical_frames(mut self, skip: bool) -> Self {
+        self.skip_identical_frames = skip;
+        self
+    }
+    /// Sets the H.264 [RateControl] mode. Ignored by backends that do not encode H.264.
+    ///
+    /// Default: `RateControl::Quality`
+    pub fn rate_control(mut self, rate_control: RateControl) -> Self {
+        self.h264.rate_control = match rate_control {
+            RateControl::ConstantQp(qp) => RateControl::ConstantQp(qp.min(MAX_QP)),
+            mode => mode,
+        };
+        self
+    }
+    /// Sets the H.264 target bitrate in bits per second. Ignored by backends that do not encode H.264.
+    ///
+    /// If unset, defaults to the OpenH264 default of 120 kbps.
+    pub fn bitrate(mut self, bps: u32) -> Self {
+        self.h264.bitrate = Some(bps);
+        self
+    }
+    /// Limits the H.264 quantization parameter chosen by rate control, from 0 (best quality)
+    /// to 51 (smallest size). Values above 51 are clamped, and `min` is lowered to `max` if greater.
+    /// Ignored by backends that do not encode H.264, and with `RateControl::ConstantQp`.
+    ///
+    /// OpenH264 raises minimums below 12 to 12.
+    ///
+    /// If unset, uses the OpenH264 defaults.
+    pub fn qp_range(mut self, min: u8, max: u8) -> Self {
+        let max = max.min(MAX_QP);
+        self.h264.qp_range = Some((min.min(max), max));
+        self
+    }
+    /// Inserts an H.264 IDR keyframe every `frames` frames, so long videos can be seeked quickly.
+    /// Ignored by backends that do not encode H.264. `0` restores the default.
+    ///
+    /// If unset, keyframes are only inserted at the start of the video and at scene changes
+    /// detected by OpenH264.
+    pub fn keyframe_interval(mut self, frames: u32) -> Self {
+        self.h264.keyframe_interval = if frames > 0 { Some(frames) } else { None };
+        self
+    }
+    /// Encodes H.264 at a constant, low quantization parameter, keeping sharp cell edges free
+    /// of visible blocking at the cost of larger files.
+    ///
+    /// Shorthand for `rate_control(RateControl::ConstantQp(16))`. Chroma is still subsampled, so
+    /// for pixel-exact frames use the `PngSequence` backend.
+    pub fn near_lossless(self) -> Self {
+        self.rate_control(RateControl::ConstantQp(NEAR_LOSSLESS_QP))
+    }
 
     /// Returns a configured video [Encoder].
-    pub fn build(self) -> Result<Encoder<T>> {
-        if Path::try_exists(&self.filepath)? {
-            return Err(Error::IoError(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                format!("output file already exists: {}", &self.filepath.display()),
-            )));
-        }
-
-        fs::File::create(&self.filepath)?;
-        log::debug!("video output file created: {}", &self.filepath.display());
+    pub fn build(mut self) -> Result<Encoder<T, C, B>> {
+        self.backend.open()?;
 
         Ok(Encoder {
-            filepath: self.filepath,
-            fps: self.fps.unwrap_or(DEFAULT_FPS) as u32,
+            frame_format: self.backend.frame_format(),
+            backend: self.backend,
+            fps: self.fps.unwrap_or(FrameRate {
+                num: DEFAULT_FPS as u32,
+                den: 1,
+            }),
             scale: self.scale,
             gridlines: self.gridlines.unwrap_or(Gridlines::Show((0, 0, 0))),
+            layout: self.layout,
+            origin: self.origin,
+            h264: self.h264,
             converter: self.converter,
-            buffer: Vec::new(),
+            element: PhantomData,
             frame_count: 0,
+            interval_count: 0,
+            keyframe_requested: false,
+            skip_identical_frames: self.skip_identical_frames,
+            pending_frame: None,
+            frame_buffer: Vec::new(),
+            palettes: Vec::new(),
             width: None,
             height: None,
-            encoder: None,
         })
     }
 }
 
 impl<T> Encoder<T> {
-    /// Returns a new [EncoderBuilder].
+    /// Returns a new [EncoderBuilder] for an MP4 video.
     ///
     /// # Arguments
     ///
@@ -144,134 +341,307 @@ impl<T> Encoder<T> {
     ///
     #[allow(clippy::new_ret_no_self)]
     pub fn new<F: AsRef<Path>>(filepath: F, converter: Box<Converter<T>>) -> EncoderBuilder<T> {
-        let filepath = filepath.as_ref().to_owned();
+        Self::with_backend(Mp4::new(filepath), converter)
+    }
 
-        if filepath.extension().unwrap() != "mp4" {
-            log::warn!("video filename extension is not `.mp4`");
-        }
+    /// Returns a new [EncoderBuilder] for an MP4 video of grid elements with a built-in [ToRgb]
+    /// conversion, e.g. `bool`, `u8` or `f32`, without a converter function.
+    ///
+    /// # Arguments
+    ///
+    /// - `filepath` - The destination file path. Warns if it does not end with the extension `.mp4`.
+    ///
+    pub fn new_default<F: AsRef<Path>>(filepath: F) -> EncoderBuilder<T>
+    where
+        T: ToRgb + 'static,
+    {
+        Self::new(filepath, Box::new(T::to_rgb))
+    }
 
-        EncoderBuilder {
-            filepath,
-            converter,
-            fps: None,
-            scale: Scaling::MaxSize(DEFAULT_SCALE_MAX_SIZE, DEFAULT_SCALE_MAX_SIZE),
-            gridlines: None,
-        }
+    /// Returns a new [EncoderBuilder] for an MP4 video kept in memory.
+    /// The MP4 bytes are returned by [close_to_vec](Encoder::close_to_vec).
+    ///
+    /// # Arguments
+    ///
+    /// - `converter` - A boxed function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+    ///
+    pub fn in_memory(converter: Box<Converter<T>>) -> EncoderBuilder<T> {
+        Self::with_backend(Mp4::in_memory(), converter)
     }
 
+    /// Returns a new [EncoderBuilder] that sends rasterized frames to a custom [VideoBackend].
+    ///
+    /// # Arguments
+    ///
+    /// - `backend` - The output backend, e.g. [Mp4].
+    /// - `converter` - A boxed function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+    ///
+    pub fn with_backend<B: VideoBackend + 'static>(
+        backend: B,
+        converter: Box<Converter<T>>,
+    ) -> EncoderBuilder<T> {
+        EncoderBuilder::from_parts(Box::new(backend), converter)
+    }
+}
+
+/// An [Encoder] that can be moved to another thread, e.g. a worker thread or a blocking async task.
+/// Created by [Encoder::with_backend_send].
+pub type SendEncoder<T> = Encoder<T, SendConverter<T>, dyn VideoBackend + Send>;
+
+impl<T> SendEncoder<T> {
+    /// Returns a new [EncoderBuilder] for a [SendEncoder], which can be moved to another thread.
+    ///
+    /// # Arguments
+    ///
+    /// - `backend` - The output backend, e.g. [Mp4]. It must be `Send`, as are all built-in backends.
+    /// - `converter` - A boxed function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+    ///   It must be `Send`.
+    ///
+    pub fn with_backend_send<B: VideoBackend + Send + 'static>(
+        backend: B,
+        converter: Box<SendConverter<T>>,
+    ) -> EncoderBuilder<T, SendConverter<T>, dyn VideoBackend + Send> {
+        EncoderBuilder::from_parts(Box::new(backend), converter)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T> Encoder<T, SyncConverter<T>> {
+    /// Returns a new [EncoderBuilder] for an MP4 video whose frames can be rasterized in
+    /// parallel with [add_frame_par](Encoder::add_frame_par). Requires the `rayon` feature.
+    ///
+    /// # Arguments
+    ///
+    /// - `filepath` - The destination file path. Warns if it does not end with the extension `.mp4`.
+    /// - `converter` - A boxed function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+    ///   It is called from several threads at once, so it must be `Sync`.
+    ///
+    pub fn new_par<F: AsRef<Path>>(
+        filepath: F,
+        converter: Box<SyncConverter<T>>,
+    ) -> EncoderBuilder<T, SyncConverter<T>> {
+        Self::with_backend_par(Mp4::new(filepath), converter)
+    }
+
+    /// Returns a new [EncoderBuilder] that sends frames to a custom [VideoBackend], and whose
+    /// frames can be rasterized in parallel with [add_frame_par](Encoder::add_frame_par).
+    /// Requires the `rayon` feature.
+    ///
+    /// # Arguments
+    ///
+    /// - `backend` - The output backend, e.g. [Mp4].
+    /// - `converter` - A boxed function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+    ///   It is called from several threads at once, so it must be `Sync`.
+    ///
+    pub fn with_backend_par<B: VideoBackend + 'static>(
+        backend: B,
+        converter: Box<SyncConverter<T>>,
+    ) -> EncoderBuilder<T, SyncConverter<T>> {
+        EncoderBuilder::from_parts(Box::new(backend), converter)
+    }
+}
+
+impl<T, C: Fn(&T) -> Rgb + ?Sized, B: VideoBackend + ?Sized> Encoder<T, C, B> {
     /// Adds a grid as a frame to the video. Returns a `Result` with the current frame count or an Error.
-    pub fn add_frame(&mut self, grid: &[Vec<T>]) -> Result<usize> {
-        let grid_width = grid.len();
-        let grid_height = grid.get(0).map_or(0, |x| x.len());
+    ///
+    /// The grid can be any [GridSource] of `T`, e.g. a `Vec<Vec<T>>`, an array of arrays, or a
+    /// flat slice wrapped in a [FlatGrid](crate::FlatGrid).
+    pub fn add_frame<G>(&mut self, grid: &G) -> Result<usize>
+    where
+        G: GridSource<Item = T> + ?Sized,
+    {
+        self.push_frame(grid, None)
+    }
 
-        // Grid shape sanity checks
-        if grid_width == 0 || grid_height == 0 {
-            return Err(Error::InvalidFrameDimensions((grid_width, grid_height)));
-        }
-        if grid.iter().skip(1).any(|y| y.len() != grid_height) {
-            return Err(Error::InconsistentGridHeight(self.frame_count));
-        }
+    /// Adds a grid as a frame that is shown for `duration` instead of one frame interval,
+    /// e.g. to hold the initial or final state. Returns a `Result` with the current frame count or an Error.
+    ///
+    /// Durations are rounded to the time base of the [VideoBackend]: 1/90000 s for [Mp4].
+    pub fn add_frame_with_duration<G>(&mut self, grid: &G, duration: Duration) -> Result<usize>
+    where
+        G: GridSource<Item = T> + ?Sized,
+    {
+        self.push_frame(grid, Some(duration))
+    }
 
-        let (grid_padding_width, grid_padding_height): (usize, usize) =
-            if let Gridlines::Show(_) = &self.gridlines {
-                let w = (grid_width - 1) * 2;
-                let h = (grid_height - 1) * 2;
-                (w, h)
-            } else {
-                (0, 0)
-            };
+    fn push_frame<G>(&mut self, grid: &G, duration: Option<Duration>) -> Result<usize>
+    where
+        G: GridSource<Item = T> + ?Sized,
+    {
+        let grid = &Oriented::new(grid, self.layout, self.origin);
+        let layout = self.frame_layout(grid)?;
 
-        let (scale_width, scale_height) = match self.scale {
-            Scaling::Uniform(scale) => (scale, scale),
-            Scaling::MaxSize(width, height) => {
-                let width_scale =
-                    (width.saturating_sub(grid_padding_width as u16)) / grid_width as u16;
-                let height_scale =
-                    (height.saturating_sub(grid_padding_height as u16)) / grid_height as u16;
+        let mut pixels = std::mem::take(&mut self.frame_buffer);
+        self.rasterize(&mut pixels, grid, &layout);
+        self.push_pixels(pixels, duration)
+    }
 
-                let adjusted_scale = width_scale.min(height_scale);
-                self.scale = Scaling::Uniform(adjusted_scale);
-                (adjusted_scale, adjusted_scale)
-            }
-            Scaling::Stretch(width, height) => {
-                let width_scale =
-                    (width.saturating_sub(grid_padding_width as u16)) / grid_width as u16;
-                let height_scale =
-                    (height.saturating_sub(grid_padding_height as u16)) / grid_height as u16;
+    /// Rasterizes the grid into `pixels` in the frame format of the backend.
+    fn rasterize<G>(&mut self, pixels: &mut Vec<u8>, grid: &G, layout: &FrameLayout)
+    where
+        G: GridSource<Item = T> + ?Sized,
+    {
+        let (scale_width, scale_height) =
+            (layout.scale_width as usize, layout.scale_height as usize);
+        match self.frame_format {
+            FrameFormat::Yuv420 => image::format_yuv_into(
+                pixels,
+                grid,
+                scale_width,
+                scale_height,
+                &*self.converter,
+                &self.gridlines,
+                &mut self.palettes,
+            ),
+            FrameFormat::Rgb => image::format_into(
+                pixels,
+                grid,
+                scale_width,
+                scale_height,
+                &*self.converter,
+                &self.gridlines,
+            ),
+        }
+    }
 
-                (width_scale, height_scale)
-            }
-        };
+    /// Validates the grid against the video, initializing the backend on the first frame.
+    fn frame_layout<G: GridSource + ?Sized>(&mut self, grid: &G) -> Result<FrameLayout> {
+        let layout = FrameLayout::new(grid, self.scale, &self.gridlines, self.frame_count)?;
+        if let Scaling::MaxSize(..) = self.scale {
+            self.scale = Scaling::Uniform(layout.scale_width);
+        }
 
-        if self.encoder.is_none() {
+        if self.width.is_none() {
             // ... then this is the first frame
 
-            let video_width = grid_width * scale_width as usize + grid_padding_width;
-            let video_height = grid_height * scale_height as usize + grid_padding_height;
-
-            // Validate OpenH264 frame requirements
-            if video_width * video_height > crate::error::OPENH264_MAX_SIZE {
-                return Err(Error::OversizedFrame((video_width, video_height)));
-            };
-            if video_width * video_height == 0 || (video_width * video_height) % 2 == 1 {
-                return Err(Error::InvalidFrameDimensions((video_width, video_height)));
+            let (width, height) = (layout.frame_width, layout.frame_height);
+            // Rasterizing to YUV 4:2:0 needs whole 2x2 chroma blocks
+            if self.frame_format == FrameFormat::Yuv420 && (width % 2 == 1 || height % 2 == 1) {
+                return Err(Error::InvalidFrameDimensions((width, height)));
             }
-
-            let config = EncoderConfig::new(video_width as u32, video_height as u32);
-            let encoder = OpenH264Encoder::with_config(config)?;
-            self.width = Some(video_width);
-            self.height = Some(video_height);
-            self.encoder = Some(encoder);
+            self.backend.init(&VideoInfo {
+                width: layout.frame_width,
+                height: layout.frame_height,
+                fps: self.fps,
+                h264: self.h264,
+            })?;
+            self.width = Some(layout.frame_width);
+            self.height = Some(layout.frame_height);
         }
 
         let video_width = self.width.unwrap();
         let video_height = self.height.unwrap();
 
-        let frame_width = grid_width * scale_width as usize + grid_padding_width;
-        let frame_height = grid_height * scale_height as usize + grid_padding_height;
-
-        if frame_width != video_width || frame_height != video_height {
+        if layout.frame_width != video_width || layout.frame_height != video_height {
             return Err(Error::FrameSizeMismatch(
                 self.frame_count,
-                (frame_width, frame_height),
+                (layout.frame_width, layout.frame_height),
                 (video_width, video_height),
             ));
         }
 
-        let rgb_stream: Vec<u8> = image::format(
-            grid,
-            scale_width as usize,
-            scale_height as usize,
-            &self.converter,
-            &self.gridlines,
-        );
-        let yuv = openh264::formats::YUVBuffer::with_rgb(video_width, video_height, &rgb_stream);
+        Ok(layout)
+    }
 
-        // Encode YUV into H.264.
-        let encoder = self.encoder.as_mut().unwrap();
-        let bitstream = encoder.encode(&yuv)?;
-        bitstream.write_vec(&mut self.buffer);
+    /// Sends rasterized `pixels` to the backend, or holds them back to merge identical frames.
+    fn push_pixels(&mut self, pixels: Vec<u8>, duration: Option<Duration>) -> Result<usize> {
+        let duration = duration.unwrap_or_else(|| {
+            // Rounded interval start times, so that frame durations do not drift
+            let n = self.interval_count;
+            self.interval_count += 1;
+            let nanos =
+                self.fps.timestamp(n + 1, 1_000_000_000) - self.fps.timestamp(n, 1_000_000_000);
+            Duration::from_nanos(nanos)
+        });
+        let frame = Frame {
+            pixels,
+            duration,
+            keyframe: std::mem::take(&mut self.keyframe_requested),
+        };
+
+        if !self.skip_identical_frames {
+            self.write_frame(frame)?;
+        } else {
+            match &mut self.pending_frame {
+                Some(pending) if !frame.keyframe && pending.pixels == frame.pixels => {
+                    pending.duration += frame.duration;
+                    self.frame_buffer = frame.pixels;
+                    log::debug!("identical video frame merged into the previous frame");
+                }
+                _ => {
+                    if let Some(pending) = self.pending_frame.replace(frame) {
+                        self.write_frame(pending)?;
+                    }
+                }
+            }
+        }
 
         self.frame_count += 1;
-        log::debug!(
-            "video frame added to {}. total: {}",
-            &self.filepath.display(),
-            &self.frame_count
-        );
+        log::debug!("video frame added. total: {}", &self.frame_count);
 
         Ok(self.frame_count)
     }
 
-    /// Writes encoded video to output file. Returns number of bytes written.
-    pub fn close(self) -> Result<()> {
+    fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        if frame.keyframe {
+            self.backend.force_keyframe()?;
+        }
+        let result = self.backend.write_frame(&frame.pixels, frame.duration);
+        // Reuse the allocation for the next frame
+        self.frame_buffer = frame.pixels;
+        result
+    }
+
+    /// Writes the frame held back by [EncoderBuilder::skip_identical_frames], if any.
+    fn flush_pending_frame(&mut self) -> Result<()> {
+        match self.pending_frame.take() {
+            Some(frame) => self.write_frame(frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Encodes the next added frame as a keyframe, e.g. at a scene change or simulation reset.
+    ///
+    /// Has no effect on backends that store every frame independently.
+    pub fn force_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// Finalizes the video and writes any remaining output through the [VideoBackend].
+    pub fn close(mut self) -> Result<()> {
         if *self.frame_count() == 0 {
             return Err(Error::NoFrames);
         };
 
-        muxer::mux(&self);
+        self.flush_pending_frame()?;
+        self.backend.finish()
+    }
 
-        log::debug!("video output written: {}", &self.filepath.display());
-        Ok(())
+    /// Finalizes the video and returns the output bytes.
+    ///
+    /// Requires a [VideoBackend] that writes to memory, e.g. from [Encoder::in_memory]. Other
+    /// backends return an error without finishing their output.
+    pub fn close_to_vec(mut self) -> Result<Vec<u8>> {
+        if *self.frame_count() == 0 {
+            return Err(Error::NoFrames);
+        };
+        if !self.backend.writes_to_memory() {
+            return Err(not_in_memory());
+        }
+
+        self.flush_pending_frame()?;
+        self.backend.finish()?;
+        self.backend.take_bytes().ok_or_else(not_in_memory)
+    }
+
+    /// Renders a grid to a still image with this encoder's converter, [Scaling], [Gridlines], [Layout] and [Origin],
+    /// without adding it to the video.
+    pub fn snapshot<G>(&self, grid: &G) -> Result<Snapshot>
+    where
+        G: GridSource<Item = T> + ?Sized,
+    {
+        let grid = Oriented::new(grid, self.layout, self.origin);
+        snapshot(&grid, &*self.converter, self.scale, self.gridlines)
     }
 
     /// Returns the current number of frames
@@ -279,3 +649,131 @@ impl<T> Encoder<T> {
         &self.frame_count
     }
 }
+
+#[cfg(feature = "rayon")]
+impl<T: Sync, B: VideoBackend + ?Sized> Encoder<T, SyncConverter<T>, B> {
+    /// Adds a grid as a frame to the video like [add_frame](Encoder::add_frame), rasterizing its
+    /// rows in parallel on the rayon thread pool. Requires the `rayon` feature.
+    pub fn add_frame_par<G>(&mut self, grid: &G) -> Result<usize>
+    where
+        G: GridSource<Item = T> + Sync + ?Sized,
+    {
+        self.push_frame_par(grid, None)
+    }
+
+    /// Adds a grid as a frame that is shown for `duration` like
+    /// [add_frame_with_duration](Encoder::add_frame_with_duration), rasterizing its rows in
+    /// parallel on the rayon thread pool. Requires the `rayon` feature.
+    pub fn add_frame_par_with_duration<G>(&mut self, grid: &G, duration: Duration) -> Result<usize>
+    where
+        G: GridSource<Item = T> + Sync + ?Sized,
+    {
+        self.push_frame_par(grid, Some(duration))
+    }
+
+    fn push_frame_par<G>(&mut self, grid: &G, duration: Option<Duration>) -> Result<usize>
+    where
+        G: GridSource<Item = T> + Sync + ?Sized,
+    {
+        let grid = &Oriented::new(grid, self.layout, self.origin);
+        let layout = self.frame_layout(grid)?;
+
+        let mut pixels = std::mem::take(&mut self.frame_buffer);
+        let (scale_width, scale_height) =
+            (layout.scale_width as usize, layout.scale_height as usize);
+        match self.frame_format {
+            FrameFormat::Yuv420 => image::format_yuv_into_par(
+                &mut pixels,
+                grid,
+                scale_width,
+                scale_height,
+                &*self.converter,
+                &self.gridlines,
+                &mut self.palettes,
+            ),
+            FrameFormat::Rgb => image::format_into_par(
+                &mut pixels,
+                grid,
+                scale_width,
+                scale_height,
+                &*self.converter,
+                &self.gridlines,
+            ),
+        }
+        self.push_pixels(pixels, duration)
+    }
+}
+
+/// Error returned by [Encoder::close_to_vec] for backends that do not write to memory.
+fn not_in_memory() -> Error {
+    Error::IoError(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "video backend does not write to memory",
+    ))
+}
+
+/// Element scale factors and output dimensions of a rasterized grid.
+pub(crate) struct FrameLayout {
+    pub(crate) scale_width: u16,
+    pub(crate) scale_height: u16,
+    pub(crate) frame_width: usize,
+    pub(crate) frame_height: usize,
+}
+
+impl FrameLayout {
+    /// Validates the grid shape and computes its layout. `frame_number` is used for error reporting.
+    pub(crate) fn new<G: GridSource + ?Sized>(
+        grid: &G,
+        scale: Scaling,
+        gridlines: &Gridlines,
+        frame_number: usize,
+    ) -> Result<Self> {
+        let grid_width = grid.width();
+        let grid_height = grid.height();
+
+        // Grid shape sanity checks
+        if grid_width == 0 || grid_height == 0 {
+            return Err(Error::InvalidFrameDimensions((grid_width, grid_height)));
+        }
+        if !grid.is_rectangular() {
+            return Err(Error::InconsistentGridHeight(frame_number));
+        }
+
+        let (grid_padding_width, grid_padding_height): (usize, usize) =
+            if let Gridlines::Show(_) = gridlines {
+                let w = (grid_width - 1) * 2;
+                let h = (grid_height - 1) * 2;
+                (w, h)
+            } else {
+                (0, 0)
+            };
+
+        let (scale_width, scale_height) = match scale {
+            Scaling::Uniform(scale) => (scale, scale),
+            Scaling::MaxSize(width, height) => {
+                let width_scale =
+                    (width.saturating_sub(grid_padding_width as u16)) / grid_width as u16;
+                let height_scale =
+                    (height.saturating_sub(grid_padding_height as u16)) / grid_height as u16;
+
+                let adjusted_scale = width_scale.min(height_scale);
+                (adjusted_scale, adjusted_scale)
+            }
+            Scaling::Stretch(width, height) => {
+                let width_scale =
+                    (width.saturating_sub(grid_padding_width as u16)) / grid_width as u16;
+                let height_scale =
+                    (height.saturating_sub(grid_padding_height as u16)) / grid_height as u16;
+
+                (width_scale, height_scale)
+            }
+        };
+
+        Ok(FrameLayout {
+            scale_width,
+            scale_height,
+            frame_width: grid_width * scale_width as usize + grid_padding_width,
+            frame_height: grid_height * scale_height as usize + grid_padding_height,
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use crate::Rgb;
+
+/// Grid element types with a built-in conversion to [Rgb], used by
+/// [Encoder::new_default](crate::Encoder::new_default) instead of a converter function.
+///
+/// - `bool`: `true` is white, `false` is black.
+/// - `u8` and `u16`: grayscale, from black at `0` to white at the type's maximum.
+/// - `f32` and `f64`: grayscale, from black at `0.0` to white at `1.0`. Values outside the range
+///   are clamped, and NaN is black.
+/// - [Rgb] and `[u8; 3]`: red, green and blue.
+/// - `[u8; 4]`: red, green, blue and alpha. Alpha is ignored.
+pub trait ToRgb {
+    /// Returns the color of this element.
+    fn to_rgb(&self) -> Rgb;
+}
+
+impl ToRgb for bool {
+    fn to_rgb(&self) -> Rgb {
+        if *self {
+            (255, 255, 255)
+        } else {
+            (0, 0, 0)
+        }
+    }
+}
+
+impl ToRgb for u8 {
+    fn to_rgb(&self) -> Rgb {
+        (*self, *self, *self)
+    }
+}
+
+impl ToRgb for u16 {
+    fn to_rgb(&self) -> Rgb {
+        // 65535 / 257 = 255, rounded to the nearest level
+        let v = ((*self as u32 + 128) / 257) as u8;
+        (v, v, v)
+    }
+}
+
+impl ToRgb for f32 {
+    fn to_rgb(&self) -> Rgb {
+        // NaN casts to 0
+        let v = (self.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (v, v, v)
+    }
+}
+
+impl ToRgb for f64 {
+    fn to_rgb(&self) -> Rgb {
+        let v = (self.clamp(0.0, 1.0) * 255.0).round() as u8;
+        (v, v, v)
+    }
+}
+
+impl ToRgb for Rgb {
+    fn to_rgb(&self) -> Rgb {
+        *self
+    }
+}
+
+impl ToRgb for [u8; 3] {
+    fn to_rgb(&self) -> Rgb {
+        (self[0], self[1], self[2])
+    }
+}
+
+impl ToRgb for [u8; 4] {
+    fn to_rgb(&self) -> Rgb {
+        (self[0], self[1], self[2])
+    }
+}
@@ -1,68 +1,457 @@
-use crate::{Gridlines, Rgb};
+use crate::{GridSource, Gridlines, Rgb};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::HashMap;
 
 /// Converts data type, transposes and flattens grid to conform with openh264::formats::rgb2yuv
-
-// Wrapped into a single function to avoid unnecessary 2D Vec allocations
-pub(crate) fn format<T, F>(
-    grid: &[Vec<T>],
+pub(crate) fn format<G, F>(
+    grid: &G,
     scale_width: usize,
     scale_height: usize,
     convert: F,
     gridlines: &Gridlines,
 ) -> Vec<u8>
 where
-    F: Fn(&T) -> Rgb,
+    G: GridSource + ?Sized,
+    F: Fn(&G::Item) -> Rgb,
 {
-    let grid_width = grid.len();
-    let grid_height = grid[0].len();
-
-    let (grid_padding_width, grid_padding_height): (usize, usize) =
-        if let Gridlines::Show(_) = &gridlines {
-            let w = (grid_width - 1) * 2;
-            let h = (grid_height - 1) * 2;
-            (w, h)
-        } else {
-            (0, 0)
+    let mut output = Vec::new();
+    format_into(
+        &mut output,
+        grid,
+        scale_width,
+        scale_height,
+        convert,
+        gridlines,
+    );
+    output
+}
+
+/// Rasterizes the grid into `output`, replacing its contents and reusing its allocation.
+//
+// Each output row is built once, calling the converter once per element, then copied for the
+// remaining scaled lines.
+pub(crate) fn format_into<G, F>(
+    output: &mut Vec<u8>,
+    grid: &G,
+    scale_width: usize,
+    scale_height: usize,
+    convert: F,
+    gridlines: &Gridlines,
+) where
+    G: GridSource + ?Sized,
+    F: Fn(&G::Item) -> Rgb,
+{
+    let raster = Raster::new(grid, scale_width, scale_height, convert, gridlines);
+    if let Some(block_len) = raster.rgb_frame(output) {
+        output
+            .chunks_mut(block_len)
+            .enumerate()
+            .for_each(|(row, block)| raster.rgb_block(block, row));
+    }
+}
+
+/// Same as [format_into], with grid rows rasterized in parallel on the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub(crate) fn format_into_par<G, F>(
+    output: &mut Vec<u8>,
+    grid: &G,
+    scale_width: usize,
+    scale_height: usize,
+    convert: F,
+    gridlines: &Gridlines,
+) where
+    G: GridSource + Sync + ?Sized,
+    F: Fn(&G::Item) -> Rgb + Sync,
+{
+    let raster = Raster::new(grid, scale_width, scale_height, convert, gridlines);
+    if let Some(block_len) = raster.rgb_frame(output) {
+        output
+            .par_chunks_mut(block_len)
+            .enumerate()
+            .for_each(|(row, block)| raster.rgb_block(block, row));
+    }
+}
+
+/// Limited range BT.601 components of a single color, identical to the RGB conversion of
+/// `openh264::formats::YUVBuffer`.
+///
+/// Chroma is kept unscaled, so that 2x2 blocks can be averaged before rounding.
+#[derive(Clone, Copy)]
+struct YuvColor {
+    y: u8,
+    u: i32,
+    v: i32,
+}
+
+impl YuvColor {
+    fn new((r, g, b): Rgb) -> Self {
+        let (r, g, b) = (r as i32, g as i32, b as i32);
+        YuvColor {
+            y: ((66 * r + 129 * g + 25 * b + (16 << 8)) >> 8) as u8,
+            u: -38 * r - 74 * g + 112 * b,
+            v: 112 * r - 94 * g - 18 * b,
+        }
+    }
+}
+
+/// Caches the YUV conversion of every color seen so far.
+#[derive(Default)]
+pub(crate) struct YuvPalette {
+    /// The first colors seen, searched linearly. Most grids use only a few colors.
+    recent: Vec<(Rgb, YuvColor)>,
+    colors: HashMap<Rgb, YuvColor>,
+}
+
+impl YuvPalette {
+    const RECENT_COLORS: usize = 16;
+
+    /// Grids with more distinct colors than this, e.g. continuous heatmaps, restart the cache
+    /// instead of growing it without bound.
+    const MAX_COLORS: usize = 1 << 16;
+
+    fn get(&mut self, rgb: Rgb) -> YuvColor {
+        if let Some((_, color)) = self.recent.iter().find(|(c, _)| *c == rgb) {
+            return *color;
+        }
+        let color = YuvColor::new(rgb);
+        if self.recent.len() < Self::RECENT_COLORS {
+            self.recent.push((rgb, color));
+            return color;
+        }
+
+        if self.colors.len() >= Self::MAX_COLORS {
+            self.colors.clear();
+        }
+        *self.colors.entry(rgb).or_insert(color)
+    }
+}
+
+/// A single row of output pixels.
+#[derive(Clone, Copy, PartialEq)]
+enum Line {
+    /// Pixels of the grid row at this index, counted from the top of the frame.
+    Elements(usize),
+    Gridline,
+}
+
+/// Converted pixels of a single output row.
+#[derive(Default)]
+struct LinePixels {
+    line: Option<Line>,
+    luma: Vec<u8>,
+    /// Unscaled chroma summed over each horizontal pair of pixels.
+    chroma: Vec<(i32, i32)>,
+    /// Last pixel, if it is the left half of an incomplete pair.
+    left: Option<YuvColor>,
+}
+
+impl LinePixels {
+    /// Starts an empty row of `line`.
+    fn clear(&mut self, line: Line) {
+        self.line = Some(line);
+        self.luma.clear();
+        self.chroma.clear();
+        self.left = None;
+    }
+
+    /// Appends `count` pixels of `color`.
+    fn push(&mut self, color: YuvColor, mut count: usize) {
+        self.luma.resize(self.luma.len() + count, color.y);
+        if let Some(left) = self.left.take() {
+            if count > 0 {
+                self.chroma.push((left.u + color.u, left.v + color.v));
+                count -= 1;
+            }
+        }
+        let pair = (color.u * 2, color.v * 2);
+        self.chroma.resize(self.chroma.len() + count / 2, pair);
+        if count % 2 == 1 {
+            self.left = Some(color);
+        }
+    }
+}
+
+/// Rasterizes the grid into `output` as planar YUV 4:2:0, replacing its contents and reusing its allocation.
+///
+/// Produces the same bytes as [format] followed by an RGB to YUV conversion, but converts each
+/// element color once and each distinct row once. Frame width and height must be multiples of 2.
+pub(crate) fn format_yuv_into<G, F>(
+    output: &mut Vec<u8>,
+    grid: &G,
+    scale_width: usize,
+    scale_height: usize,
+    convert: F,
+    gridlines: &Gridlines,
+    palettes: &mut Vec<YuvPalette>,
+) where
+    G: GridSource + ?Sized,
+    F: Fn(&G::Item) -> Rgb,
+{
+    let raster = Raster::new(grid, scale_width, scale_height, convert, gridlines);
+    if let Some((luma, u_plane, v_plane)) = raster.yuv_planes(output) {
+        palettes.resize_with(1, YuvPalette::default);
+        raster.yuv_band(luma, u_plane, v_plane, 0, &mut palettes[0]);
+    }
+}
+
+/// Same as [format_yuv_into], with the frame split into horizontal bands rasterized in parallel
+/// on the rayon thread pool, each with its own palette from `palettes`.
+#[cfg(feature = "rayon")]
+pub(crate) fn format_yuv_into_par<G, F>(
+    output: &mut Vec<u8>,
+    grid: &G,
+    scale_width: usize,
+    scale_height: usize,
+    convert: F,
+    gridlines: &Gridlines,
+    palettes: &mut Vec<YuvPalette>,
+) where
+    G: GridSource + Sync + ?Sized,
+    F: Fn(&G::Item) -> Rgb + Sync,
+{
+    let raster = Raster::new(grid, scale_width, scale_height, convert, gridlines);
+    let frame_width = raster.frame_width;
+    // Bands cover whole pairs of output rows, which share a row of chroma
+    let pairs = raster.frame_height() / 2;
+    let Some((luma, u_plane, v_plane)) = raster.yuv_planes(output) else {
+        return;
+    };
+    let max_bands = pairs.div_ceil(MIN_BAND_PAIRS).max(1);
+    let bands = rayon::current_num_threads().clamp(1, max_bands);
+    let band_pairs = pairs.div_ceil(bands).max(1);
+    palettes.resize_with(bands, YuvPalette::default);
+
+    let chroma_width = frame_width / 2;
+    luma.par_chunks_mut(band_pairs * 2 * frame_width)
+        .zip(u_plane.par_chunks_mut(band_pairs * chroma_width))
+        .zip(v_plane.par_chunks_mut(band_pairs * chroma_width))
+        .zip(palettes.par_iter_mut())
+        .enumerate()
+        .for_each(|(band, (((luma, u_plane), v_plane), palette))| {
+            raster.yuv_band(luma, u_plane, v_plane, band * band_pairs, palette)
+        });
+}
+
+/// Smallest number of output row pairs worth rasterizing on a separate thread.
+#[cfg(feature = "rayon")]
+const MIN_BAND_PAIRS: usize = 16;
+
+/// A grid and the options that rasterize it, shared by every row of a frame.
+struct Raster<'a, G: ?Sized, F> {
+    grid: &'a G,
+    scale_width: usize,
+    scale_height: usize,
+    convert: F,
+    /// Gridline color, if shown.
+    gridline: Option<Rgb>,
+    frame_width: usize,
+}
+
+impl<'a, G, F> Raster<'a, G, F>
+where
+    G: GridSource + ?Sized,
+    F: Fn(&G::Item) -> Rgb,
+{
+    fn new(
+        grid: &'a G,
+        scale_width: usize,
+        scale_height: usize,
+        convert: F,
+        gridlines: &Gridlines,
+    ) -> Self {
+        let gridline = match gridlines {
+            Gridlines::Show(color) => Some(*color),
+            Gridlines::Hide => None,
         };
+        let mut raster = Raster {
+            grid,
+            scale_width,
+            scale_height,
+            convert,
+            gridline,
+            frame_width: 0,
+        };
+        raster.frame_width = grid.width() * (scale_width + raster.gap()) - raster.gap();
+        raster
+    }
 
-    let frame_width = grid_width * scale_width + grid_padding_width;
-    let frame_height = grid_height * scale_height + grid_padding_height;
-
-    let mut output: Vec<u8> = Vec::with_capacity(frame_width * frame_height * 3);
-    (0..grid_height)
-        .rev() // For OpenH264, (0,0) is upper-left corner
-        .for_each(|y| {
-            let row_step_iter = (0..grid_width).flat_map(|x| -> Vec<Rgb> {
-                let rgb_value: Rgb = convert(&grid[x][y]);
-                let cell_width: Vec<Rgb> = vec![rgb_value; scale_width];
-                if let Gridlines::Show(color) = gridlines {
-                    if x != grid_width - 1 {
-                        let grid_divider = color;
-                        // width must remain a multiple of 2
-                        return [cell_width, vec![*grid_divider, *grid_divider]].concat();
-                    }
+    /// Gridline width in pixels. Gridlines are 2 pixels, so dimensions remain a multiple of 2.
+    fn gap(&self) -> usize {
+        match self.gridline {
+            Some(_) => 2,
+            None => 0,
+        }
+    }
+
+    /// Number of output rows of a grid row and the gridline below it.
+    fn block_height(&self) -> usize {
+        self.scale_height + self.gap()
+    }
+
+    fn frame_height(&self) -> usize {
+        self.grid.height() * self.block_height() - self.gap()
+    }
+
+    /// Resizes `output` to a packed RGB frame, and returns the length of the output rows of a
+    /// grid row and its gridline rows. Returns `None` if the frame is empty.
+    fn rgb_frame(&self, output: &mut Vec<u8>) -> Option<usize> {
+        let row_len = self.frame_width * 3;
+        output.clear();
+        output.resize(row_len * self.frame_height(), 0);
+        (!output.is_empty()).then(|| row_len * self.block_height())
+    }
+
+    /// Resizes `output` to a planar YUV 4:2:0 frame, and returns its Y, U and V planes.
+    /// Returns `None` if the frame is empty.
+    fn yuv_planes<'o>(
+        &self,
+        output: &'o mut Vec<u8>,
+    ) -> Option<(&'o mut [u8], &'o mut [u8], &'o mut [u8])> {
+        let luma_len = self.frame_width * self.frame_height();
+        let chroma_len = luma_len / 4;
+        output.clear();
+        output.resize(luma_len + chroma_len * 2, 0);
+        if luma_len == 0 {
+            return None;
+        }
+        let (luma, chroma) = output.split_at_mut(luma_len);
+        let (u_plane, v_plane) = chroma.split_at_mut(chroma_len);
+        Some((luma, u_plane, v_plane))
+    }
+
+    /// Returns the contents of output row `r`, counted from the top of the frame.
+    fn line(&self, r: usize) -> Line {
+        let block_height = self.block_height();
+        if r % block_height < self.scale_height {
+            Line::Elements(r / block_height)
+        } else {
+            Line::Gridline
+        }
+    }
+
+    /// Returns the elements of grid row `row`, counted from the top of the frame, from left to right.
+    fn elements(&self, row: usize) -> impl Iterator<Item = &G::Item> {
+        // For OpenH264, (0,0) is upper-left corner
+        let y = self.grid.height() - 1 - row;
+        (0..self.grid.width()).map(move |x| self.grid.cell(x, y))
+    }
+
+    /// Writes the packed RGB rows of grid row `row` to `block`, followed by its gridline rows.
+    fn rgb_block(&self, block: &mut [u8], row: usize) {
+        let row_len = self.frame_width * 3;
+        let (elements, gridlines) = block.split_at_mut(row_len * self.scale_height);
+
+        if self.scale_height > 0 {
+            let mut pixels = elements[..row_len].chunks_exact_mut(3);
+            let last = self.grid.width() - 1;
+            for (x, element) in self.elements(row).enumerate() {
+                let (r, g, b) = (self.convert)(element);
+                for pixel in pixels.by_ref().take(self.scale_width) {
+                    pixel.copy_from_slice(&[r, g, b]);
                 }
-                cell_width
-            });
-
-            for _ in 0..scale_height {
-                for rgb in row_step_iter.clone() {
-                    output.push(rgb.0);
-                    output.push(rgb.1);
-                    output.push(rgb.2);
+                if let Some((r, g, b)) = self.gridline.filter(|_| x != last) {
+                    for pixel in pixels.by_ref().take(2) {
+                        pixel.copy_from_slice(&[r, g, b]);
+                    }
                 }
             }
-            if let Gridlines::Show(color) = gridlines {
-                if y != 0 {
-                    // height must remain a multiple of 2
-                    for _ in 0..(frame_width * 2) {
-                        output.push(color.0);
-                        output.push(color.1);
-                        output.push(color.2);
+            for copy in 1..self.scale_height {
+                elements.copy_within(..row_len, copy * row_len);
+            }
+        }
+
+        if let Some((r, g, b)) = self.gridline {
+            for pixel in gridlines.chunks_exact_mut(3) {
+                pixel.copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+
+    /// Fills `pixels` with grid row `row`, counted from the top of the frame.
+    fn fill_elements(&self, pixels: &mut LinePixels, row: usize, palette: &mut YuvPalette) {
+        pixels.clear(Line::Elements(row));
+        let gridline = self.gridline.map(|color| palette.get(color));
+        let last = self.grid.width() - 1;
+        for (x, element) in self.elements(row).enumerate() {
+            pixels.push(palette.get((self.convert)(element)), self.scale_width);
+            if let Some(gridline) = gridline.filter(|_| x != last) {
+                pixels.push(gridline, 2);
+            }
+        }
+    }
+
+    /// Writes the YUV 4:2:0 planes of a band of output rows, starting at row pair `first_pair`.
+    fn yuv_band(
+        &self,
+        luma: &mut [u8],
+        u_plane: &mut [u8],
+        v_plane: &mut [u8],
+        first_pair: usize,
+        palette: &mut YuvPalette,
+    ) {
+        let frame_width = self.frame_width;
+        let chroma_width = frame_width / 2;
+
+        let mut gridline = LinePixels::default();
+        if let Some(color) = self.gridline {
+            gridline.clear(Line::Gridline);
+            gridline.push(YuvColor::new(color), frame_width);
+        }
+        // The two most recent element rows; a pair of output rows spans at most two
+        let mut current = LinePixels::default();
+        let mut previous = LinePixels::default();
+        // Most recent pair of rows, and of gridline rows, for copying chroma
+        let mut last_pair: Option<(Line, Line)> = None;
+        let mut gridline_pair: Option<usize> = None;
+
+        for j in 0..u_plane.len() / chroma_width {
+            let r = (first_pair + j) * 2;
+            let pair = (self.line(r), self.line(r + 1));
+            for line in [pair.0, pair.1] {
+                if let Line::Elements(row) = line {
+                    if current.line != Some(line) && previous.line != Some(line) {
+                        std::mem::swap(&mut current, &mut previous);
+                        self.fill_elements(&mut current, row, palette);
                     }
                 }
             }
-        });
+            let pixels = |line: Line| match line {
+                Line::Gridline => &gridline,
+                line if current.line == Some(line) => &current,
+                _ => &previous,
+            };
+            let (top, bottom) = (pixels(pair.0), pixels(pair.1));
 
-    output
+            let luma_rows = &mut luma[j * 2 * frame_width..(j + 1) * 2 * frame_width];
+            let (luma_top, luma_bottom) = luma_rows.split_at_mut(frame_width);
+            luma_top.copy_from_slice(&top.luma);
+            luma_bottom.copy_from_slice(&bottom.luma);
+
+            let chroma_row = j * chroma_width;
+            let copy_from = match pair {
+                // Same rows as the previous pair, e.g. within a scaled element
+                _ if last_pair == Some(pair) => Some(chroma_row - chroma_width),
+                (Line::Gridline, Line::Gridline) => gridline_pair.replace(chroma_row),
+                _ => None,
+            };
+            let chroma_range = chroma_row..chroma_row + chroma_width;
+            match copy_from {
+                Some(start) => {
+                    u_plane.copy_within(start..start + chroma_width, chroma_row);
+                    v_plane.copy_within(start..start + chroma_width, chroma_row);
+                }
+                None => {
+                    let dst = u_plane[chroma_range.clone()]
+                        .iter_mut()
+                        .zip(&mut v_plane[chroma_range]);
+                    for ((u, v), (t, b)) in dst.zip(top.chroma.iter().zip(&bottom.chroma)) {
+                        *u = ((t.0 + b.0 + (128 << 10)) >> 10) as u8;
+                        *v = ((t.1 + b.1 + (128 << 10)) >> 10) as u8;
+                    }
+                }
+            }
+            last_pair = Some(pair);
+        }
+    }
 }
@@ -0,0 +1,107 @@
+use super::{image, FrameLayout};
+use crate::{Error, GridSource, Gridlines, Result, Rgb, Scaling};
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// A single rasterized grid, rendered without creating a video.
+///
+/// Created by [snapshot](crate::snapshot) or [Encoder::snapshot](crate::Encoder::snapshot).
+/// Pixels are identical to the corresponding video frame before encoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+}
+
+/// Renders a single grid to a still image, applying [Scaling] and [Gridlines] as a video frame would.
+///
+/// # Arguments
+///
+/// - `grid` - The grid to render, any [GridSource]. The outer vector of a `Vec<Vec<T>>` translates to the X-axis.
+/// - `converter` - A function that maps grid type to a tuple, `&T -> (u8, u8, u8)` containing Red, Green and Blue values.
+/// - `scale` - The [Scaling] option.
+/// - `gridlines` - The [Gridlines] option.
+///
+pub fn snapshot<G, F>(
+    grid: &G,
+    converter: F,
+    scale: Scaling,
+    gridlines: Gridlines,
+) -> Result<Snapshot>
+where
+    G: GridSource + ?Sized,
+    F: Fn(&G::Item) -> Rgb,
+{
+    let layout = FrameLayout::new(grid, scale, &gridlines, 0)?;
+    if layout.frame_width * layout.frame_height == 0 {
+        return Err(Error::InvalidFrameDimensions((
+            layout.frame_width,
+            layout.frame_height,
+        )));
+    }
+
+    let rgb = image::format(
+        grid,
+        layout.scale_width as usize,
+        layout.scale_height as usize,
+        converter,
+        &gridlines,
+    );
+
+    Ok(Snapshot {
+        width: layout.frame_width,
+        height: layout.frame_height,
+        rgb,
+    })
+}
+
+impl Snapshot {
+    /// Returns the image width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the image height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the packed RGB bytes, ordered row by row from the top-left corner.
+    pub fn as_rgb(&self) -> &[u8] {
+        &self.rgb
+    }
+
+    /// Writes the image in binary PPM (P6) format.
+    pub fn write_ppm<W: Write>(&self, mut w: W) -> Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.rgb)?;
+        Ok(())
+    }
+
+    /// Saves the image to a new binary PPM file. Returns an error if the file already exists.
+    pub fn save_ppm<P: AsRef<Path>>(&self, filepath: P) -> Result<()> {
+        let mut w = BufWriter::new(crate::backend::create_file(filepath.as_ref())?);
+        self.write_ppm(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the image in PNG format. Requires the `png` feature.
+    #[cfg(feature = "png")]
+    pub fn write_png<W: Write>(&self, w: W) -> Result<()> {
+        crate::backend::write_png(w, self.width, self.height, &self.rgb)
+    }
+
+    /// Saves the image to a new PNG file. Returns an error if the file already exists.
+    /// Requires the `png` feature.
+    #[cfg(feature = "png")]
+    pub fn save_png<P: AsRef<Path>>(&self, filepath: P) -> Result<()> {
+        let mut w = BufWriter::new(crate::backend::create_file(filepath.as_ref())?);
+        self.write_png(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
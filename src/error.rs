@@ -12,6 +12,21 @@ pub enum Error {
     /// Error resulting from an OpenH264 operation.
     #[error("{0}")]
     Openh264Error(#[from] openh264::Error),
+    /// Error returned by the minimp4 MP4 muxer.
+    ///
+    /// Wraps: minimp4 `MP4E_STATUS_*` code
+    #[error("mp4 muxer failed: {} (status {0})", muxer_status_description(*.0))]
+    MuxerError(i32),
+    /// Error resulting from a GIF encoding operation. Only returned with the `gif` feature.
+    ///
+    /// Wraps: `gif::EncodingError`
+    #[error("{0}")]
+    GifError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Error resulting from a PNG encoding operation. Only returned with the `png` feature.
+    ///
+    /// Wraps: `png::EncodingError`
+    #[error("{0}")]
+    PngError(#[source] Box<dyn std::error::Error + Send + Sync>),
     /// Frame width×height > [OPENH264_MAX_SIZE].
     ///
     /// Wraps: `(frame_width, frame_height)`
@@ -26,6 +41,11 @@ pub enum Error {
     /// Wraps: `(frame_width, frame_height)`
     #[error("openh264 requires frame width and height to both be above 0 and a multiple of 2. (w, h)={0:?}")]
     InvalidFrameDimensions((usize, usize)),
+    /// Frame dimensions are not supported by the output backend.
+    ///
+    /// Wraps: `(frame_width, frame_height)`
+    #[error("frame dimensions are not supported by the output backend. (w, h)={0:?}")]
+    UnsupportedFrameDimensions((usize, usize)),
     /// Video has zero frames.
     #[error("video has zero frames")]
     NoFrames,
@@ -40,3 +60,28 @@ pub enum Error {
     #[error("at least one column in frame {0} differs from other columns")]
     InconsistentGridHeight(usize),
 }
+
+#[cfg(feature = "gif")]
+impl From<gif::EncodingError> for Error {
+    fn from(error: gif::EncodingError) -> Self {
+        Error::GifError(Box::new(error))
+    }
+}
+
+#[cfg(feature = "png")]
+impl From<png::EncodingError> for Error {
+    fn from(error: png::EncodingError) -> Self {
+        Error::PngError(Box::new(error))
+    }
+}
+
+/// Describes a minimp4 `MP4E_STATUS_*` code
+fn muxer_status_description(status: i32) -> &'static str {
+    match status {
+        -1 => "bad arguments",
+        -2 => "out of memory",
+        -3 => "file write error",
+        -4 => "only one decoder specific info allowed",
+        _ => "unknown error",
+    }
+}
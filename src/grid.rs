@@ -0,0 +1,261 @@
+use crate::{Layout, Origin};
+
+/// A rectangular grid of elements that can be added as a video frame.
+///
+/// Columns are indexed by `x` from left to right, and rows by `y` from the bottom of the frame
+/// to the top. This matches a `Vec<Vec<T>>` grid, where the outer vector translates to the
+/// X-axis and the inner vectors translate to the Y-axis.
+///
+/// [EncoderBuilder::layout](crate::EncoderBuilder::layout) and
+/// [EncoderBuilder::origin](crate::EncoderBuilder::origin) change how `x` and `y` are shown,
+/// e.g. `Layout::RowMajor` shows `x` as the row and `y` as the column.
+///
+/// Implemented for `Vec<Vec<T>>`, `[Vec<T>]`, arrays of arrays `[[T; H]; W]`, and flat slices
+/// wrapped in a [FlatGrid].
+pub trait GridSource {
+    /// Grid element type, mapped to [Rgb](crate::Rgb) by the [Converter](crate::Converter).
+    type Item;
+
+    /// Returns the number of columns.
+    fn width(&self) -> usize;
+
+    /// Returns the number of rows.
+    fn height(&self) -> usize;
+
+    /// Returns the element in column `x` and row `y`.
+    ///
+    /// Only called with `x < width()` and `y < height()`, after
+    /// [is_rectangular](GridSource::is_rectangular) has returned `true`.
+    fn cell(&self, x: usize, y: usize) -> &Self::Item;
+
+    /// Returns whether every column has [height](GridSource::height) elements.
+    ///
+    /// Checked once per frame. Grids whose shape is fixed by their type can keep the default, `true`.
+    fn is_rectangular(&self) -> bool {
+        true
+    }
+}
+
+impl<G: GridSource + ?Sized> GridSource for &G {
+    type Item = G::Item;
+
+    fn width(&self) -> usize {
+        (**self).width()
+    }
+
+    fn height(&self) -> usize {
+        (**self).height()
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &Self::Item {
+        (**self).cell(x, y)
+    }
+
+    fn is_rectangular(&self) -> bool {
+        (**self).is_rectangular()
+    }
+}
+
+impl<T> GridSource for [Vec<T>] {
+    type Item = T;
+
+    fn width(&self) -> usize {
+        self.len()
+    }
+
+    fn height(&self) -> usize {
+        self.first().map_or(0, |column| column.len())
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &T {
+        &self[x][y]
+    }
+
+    fn is_rectangular(&self) -> bool {
+        let height = self.height();
+        self.iter().skip(1).all(|column| column.len() == height)
+    }
+}
+
+impl<T> GridSource for Vec<Vec<T>> {
+    type Item = T;
+
+    fn width(&self) -> usize {
+        self.as_slice().width()
+    }
+
+    fn height(&self) -> usize {
+        self.as_slice().height()
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &T {
+        &self[x][y]
+    }
+
+    fn is_rectangular(&self) -> bool {
+        self.as_slice().is_rectangular()
+    }
+}
+
+impl<T, const H: usize> GridSource for [[T; H]] {
+    type Item = T;
+
+    fn width(&self) -> usize {
+        self.len()
+    }
+
+    fn height(&self) -> usize {
+        H
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &T {
+        &self[x][y]
+    }
+}
+
+impl<T, const W: usize, const H: usize> GridSource for [[T; H]; W] {
+    type Item = T;
+
+    fn width(&self) -> usize {
+        W
+    }
+
+    fn height(&self) -> usize {
+        H
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &T {
+        &self[x][y]
+    }
+}
+
+/// A grid viewed with a [Layout] and [Origin], indexed from the bottom-left corner of the frame.
+pub(crate) struct Oriented<'a, G: ?Sized> {
+    grid: &'a G,
+    layout: Layout,
+    origin: Origin,
+    width: usize,
+    height: usize,
+}
+
+impl<'a, G: GridSource + ?Sized> Oriented<'a, G> {
+    pub(crate) fn new(grid: &'a G, layout: Layout, origin: Origin) -> Self {
+        let (width, height) = match layout {
+            Layout::ColumnMajor => (grid.width(), grid.height()),
+            Layout::RowMajor => (grid.height(), grid.width()),
+        };
+        Oriented {
+            grid,
+            layout,
+            origin,
+            width,
+            height,
+        }
+    }
+}
+
+impl<G: GridSource + ?Sized> GridSource for Oriented<'_, G> {
+    type Item = G::Item;
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &Self::Item {
+        let x = match self.origin {
+            Origin::BottomLeft | Origin::TopLeft => x,
+            Origin::BottomRight | Origin::TopRight => self.width - 1 - x,
+        };
+        let y = match self.origin {
+            Origin::BottomLeft | Origin::BottomRight => y,
+            Origin::TopLeft | Origin::TopRight => self.height - 1 - y,
+        };
+        match self.layout {
+            Layout::ColumnMajor => self.grid.cell(x, y),
+            Layout::RowMajor => self.grid.cell(y, x),
+        }
+    }
+
+    fn is_rectangular(&self) -> bool {
+        self.grid.is_rectangular()
+    }
+}
+
+/// A grid stored in a flat slice, one row after another.
+///
+/// The first `width` elements are the bottom row, from left to right, so the element in column
+/// `x` and row `y` is `cells[y * width + x]`.
+///
+/// ```
+/// use gridvid::{FlatGrid, GridSource};
+///
+/// let cells = vec![0u8; 6 * 4];
+/// let grid = FlatGrid::new(&cells, 6);
+/// assert_eq!((grid.width(), grid.height()), (6, 4));
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct FlatGrid<'a, T> {
+    cells: &'a [T],
+    width: usize,
+}
+
+impl<'a, T> FlatGrid<'a, T> {
+    /// Wraps `cells`, a grid of rows with `width` elements each.
+    ///
+    /// If the length of `cells` is not a multiple of `width`, adding the grid as a frame returns
+    /// [Error::InconsistentGridHeight](crate::Error::InconsistentGridHeight).
+    pub fn new(cells: &'a [T], width: usize) -> Self {
+        FlatGrid { cells, width }
+    }
+}
+
+impl<T> GridSource for FlatGrid<'_, T> {
+    type Item = T;
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.cells.len().checked_div(self.width).unwrap_or(0)
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &T {
+        &self.cells[y * self.width + x]
+    }
+
+    fn is_rectangular(&self) -> bool {
+        self.cells
+            .len()
+            .checked_rem(self.width)
+            .unwrap_or(self.cells.len())
+            == 0
+    }
+}
+
+/// An `ndarray` matrix, indexed by its own strides. Requires the `ndarray` feature.
+///
+/// Like the outer vector of a `Vec<Vec<T>>`, axis 0 translates to the X-axis, so `array[[x, y]]`
+/// is shown in column `x` and row `y`. Matrices indexed as `[[row, column]]` are shown upright
+/// with [Layout::RowMajor] and [Origin::TopLeft]. Views such as `array.t()` or
+/// `array.slice(s![.., ..;-1])` are read in place, without copying.
+#[cfg(feature = "ndarray")]
+impl<S: ndarray::Data> GridSource for ndarray::ArrayBase<S, ndarray::Ix2> {
+    type Item = S::Elem;
+
+    fn width(&self) -> usize {
+        self.nrows()
+    }
+
+    fn height(&self) -> usize {
+        self.ncols()
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &S::Elem {
+        &self[[x, y]]
+    }
+}
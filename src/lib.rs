@@ -2,6 +2,7 @@
 //! Gridvid is a Rust wrapper library for rendering MP4 videos from 2D vectors using a minimal interface.
 //!
 //! The outer vector translates to the X-axis and the inner vectors translate to the Y-axis.
+//! Arrays of arrays, flat slices and other storage can be added through the [GridSource] trait.
 //!
 //! ## Basic Usage
 //!
@@ -38,17 +39,22 @@
 //!
 //! <video controls style="display: block; max-width: 360px" src="https://user-images.githubusercontent.com/65624699/224349598-32c3c34c-fde2-4194-a398-fe7cde6b3335.mp4"></video>
 //!
+//! Grids of `bool`, grayscale, float or color elements do not need a converter function:
+//! [Encoder::new_default] converts them with the built-in [ToRgb] trait.
+//!
 //! ## Options Summary
 //! ```
 //! # fn main() -> gridvid::Result<()> {
 //! #
-//!     use gridvid::{Encoder, Gridlines, Scaling};
+//!     use gridvid::{Encoder, Gridlines, Layout, Origin, RateControl, Scaling};
 //!
 //! #    let convert = |&b: &bool| if b { (0, 0, 255) } else { (0, 0, 0) };
 //! #    let filename = std::env::temp_dir().join("gridvid_demo.mp4");
 //! #    
 //!     let mut video = Encoder::new(&filename, Box::new(convert))
 //!         .fps(20)    // Set video frame rate to 20 fps
+//!         .frame_rate(30000, 1001)  // Set video frame rate to 29.97 fps
+//!         .skip_identical_frames(true)  // Extend the previous frame instead of repeating it
 //!
 //!         // Video Frame Scaling options
 //!         .scale(Scaling::Uniform(16))        // Upscale by a factor of 16
@@ -58,6 +64,17 @@
 //!         // Gridline options
 //!         .gridlines(Gridlines::Show((255,255,255)))  // Set gridline color to white
 //!         .gridlines(Gridlines::Hide)                 // Hide gridlines
+//!
+//!         // Grid orientation options
+//!         .layout(Layout::RowMajor)   // Index grids as `grid[y][x]`
+//!         .origin(Origin::TopLeft)    // Show the first row at the top
+//!
+//!         // H.264 quality options
+//!         .rate_control(RateControl::Bitrate)  // Keep close to the target bitrate
+//!         .bitrate(2_000_000)                  // Set target bitrate to 2 Mbps
+//!         .qp_range(12, 36)                    // Limit quantization parameter range
+//!         .near_lossless()                     // Constant low QP, no blocking on sharp edges
+//!         .keyframe_interval(120)              // Insert a keyframe every 120 frames
 //!         .build()?;
 //! #
 //! #    // Remove file so doctest can be repeated
@@ -71,13 +88,54 @@
 //! - Video frame rate is 4 [fps].
 //! - Black gridlines are inserted in between elements: [`Gridlines(0,0,0)`](Gridlines)
 //! - Video is scaled to 720x720: [`MaxSize(720, 720)`](Scaling)
+//! - Output is an H.264 MP4 file: [`backend::Mp4`]
 //!
 //! [fps]: EncoderBuilder::fps
+//!
+//! # Output Formats
+//! Frames are written through a [VideoBackend], set with [Encoder::with_backend].
+//! - [`backend::Mp4`]: H.264 MP4 video, to a file, any seekable writer or memory. Used by [Encoder::new] and [Encoder::in_memory].
+//! - [`backend::H264`]: Raw H.264 Annex-B elementary stream, to a file or any writer.
+//! - `backend::Gif`: Animated GIF. Requires the `gif` feature.
+//! - `backend::PngSequence`: Numbered PNG files in a directory. Requires the `png` feature.
+//! - [`backend::Y4m`]: Uncompressed YUV4MPEG2 video.
+//!
+//! Wrapping a backend in [`backend::Background`] encodes frames on a worker thread, while the
+//! next grid is computed.
+//! [`backend::Mp4::parallel_segments`] encodes runs of frames on separate threads instead, and
+//! stitches them into a single MP4.
+//!
+//! A single grid can be rendered to a still PPM or PNG image with [snapshot] or [Encoder::snapshot].
+//!
+//! # Parallel Rasterization
+//! With the `rayon` feature, encoders created by `Encoder::new_par` or `Encoder::with_backend_par`
+//! take a converter that can be called from several threads at once. Their `add_frame_par`
+//! rasterizes the rows of a `Sync` grid in parallel on the rayon thread pool. Other encoders are
+//! unchanged.
+//!
+//! A [SendEncoder], created by [Encoder::with_backend_send] with a `Send` backend and converter,
+//! can be moved to a worker thread or a blocking async task.
+//!
+//! # ndarray
+//! With the `ndarray` feature, `Array2<T>` and `ArrayView2<T>` implement [GridSource] and can be
+//! passed to [Encoder::add_frame] directly. Axis 0 translates to the X-axis, so matrices indexed
+//! as `[[row, column]]` need [`Layout::RowMajor`] and [`Origin::TopLeft`] to be shown upright.
 
+pub mod backend;
 mod encoder;
 mod error;
+mod grid;
 
 #[doc(inline)]
-pub use encoder::{Converter, Encoder, EncoderBuilder, Gridlines, Result, Rgb, Scaling};
+pub use backend::VideoBackend;
+#[cfg(feature = "rayon")]
+#[doc(inline)]
+pub use encoder::SyncConverter;
+#[doc(inline)]
+pub use encoder::{
+    snapshot, Converter, Encoder, EncoderBuilder, Gridlines, Layout, Origin, RateControl, Result,
+    Rgb, Scaling, SendConverter, SendEncoder, Snapshot, ToRgb,
+};
 #[doc(inline)]
 pub use error::{Error, OPENH264_MAX_SIZE};
+pub use grid::{FlatGrid, GridSource};
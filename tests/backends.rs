@@ -0,0 +1,995 @@
+mod common;
+use common::*;
+
+use gridvid::backend::{FrameFormat, FrameRate, VideoBackend, VideoInfo};
+use gridvid::{Encoder, EncoderBuilder, Gridlines, RateControl, Result, Scaling};
+use openh264::formats::{YUVBuffer, YUVSource};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Recorded {
+    info: Option<VideoInfo>,
+    frames: Vec<Vec<u8>>,
+    durations: Vec<Duration>,
+    finished: bool,
+}
+
+struct RecordingBackend(Arc<Mutex<Recorded>>);
+
+impl VideoBackend for RecordingBackend {
+    fn init(&mut self, info: &VideoInfo) -> Result<()> {
+        self.0.lock().unwrap().info = Some(*info);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, rgb: &[u8], duration: Duration) -> Result<()> {
+        let mut recorded = self.0.lock().unwrap();
+        recorded.frames.push(rgb.to_vec());
+        recorded.durations.push(duration);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.0.lock().unwrap().finished = true;
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_backend_receives_frames() -> Result<()> {
+    env_logger_init();
+
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    let mut video = Encoder::with_backend(
+        RecordingBackend(Arc::clone(&recorded)),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(2))
+    .gridlines(Gridlines::Hide)
+    .fps(10)
+    .build()?;
+
+    // 3 columns, 2 rows with the lower-left element switched on
+    let mut grid = vec![vec![GridItem::Off; 2]; 3];
+    grid[0][0] = GridItem::On;
+    video.add_frame(&grid)?;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let recorded = recorded.lock().unwrap();
+    let info = recorded.info.unwrap();
+    assert_eq!((info.width, info.height), (6, 4));
+    assert_eq!(info.fps, FrameRate { num: 10, den: 1 });
+    assert_eq!(recorded.frames.len(), 2);
+    assert!(recorded.finished);
+
+    let frame = &recorded.frames[0];
+    assert_eq!(frame.len(), 6 * 4 * 3);
+    let pixel = |x: usize, y: usize| &frame[(y * 6 + x) * 3..(y * 6 + x) * 3 + 3];
+    assert_eq!(pixel(0, 3), &[128, 0, 255]);
+    assert_eq!(pixel(1, 2), &[128, 0, 255]);
+    assert_eq!(pixel(0, 0), &[0, 0, 0]);
+    assert_eq!(pixel(2, 3), &[0, 0, 0]);
+
+    Ok(())
+}
+
+#[test]
+fn identical_frames_are_merged() -> Result<()> {
+    env_logger_init();
+
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    let mut video = Encoder::with_backend(
+        RecordingBackend(Arc::clone(&recorded)),
+        Box::new(griditem_to_rgb),
+    )
+    .fps(4)
+    .skip_identical_frames(true)
+    .build()?;
+
+    let off = vec![vec![GridItem::Off; 3]; 3];
+    let mut on = off.clone();
+    on[1][1] = GridItem::On;
+    for grid in [&off, &off, &off, &on, &on] {
+        video.add_frame(grid)?;
+    }
+    video.add_frame_with_duration(&on, Duration::from_secs(1))?;
+    // A forced keyframe starts a new frame even if it is identical
+    video.force_keyframe();
+    video.add_frame(&on)?;
+    assert_eq!(*video.frame_count(), 7);
+    video.close()?;
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.frames.len(), 3);
+    assert_ne!(recorded.frames[0], recorded.frames[1]);
+    assert_eq!(
+        recorded.durations,
+        vec![
+            Duration::from_millis(750),
+            Duration::from_millis(1500),
+            Duration::from_millis(250)
+        ]
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn gif_output() -> Result<()> {
+    env_logger_init();
+
+    let filename = TempPath::new(&"gif_output.gif");
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Gif::new(&filename),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(4))
+    .gridlines(Gridlines::Show((255, 255, 255)))
+    .fps(3)
+    .build()?;
+
+    let mut grid = vec![vec![GridItem::Off; 4]; 5];
+    for i in 0..4 {
+        grid[i][i] = GridItem::On;
+        video.add_frame(&grid)?;
+    }
+    video.close()?;
+
+    let mut decoder = gif::DecodeOptions::new()
+        .read_info(std::fs::File::open(&filename)?)
+        .unwrap();
+    assert_eq!((decoder.width(), decoder.height()), (5 * 4 + 8, 4 * 4 + 6));
+    // Black, white and violet in order of appearance, padded to a power of 2
+    assert_eq!(
+        decoder.global_palette().unwrap(),
+        &[0, 0, 0, 255, 255, 255, 128, 0, 255, 0, 0, 0]
+    );
+
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        delays.push(frame.delay);
+    }
+    assert_eq!(delays, vec![33, 34, 33, 33]);
+
+    Ok(())
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn gif_frames_with_new_colors_use_local_palettes() -> Result<()> {
+    env_logger_init();
+
+    let filename = TempPath::new(&"gif_frames_with_new_colors_use_local_palettes.gif");
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Gif::new(&filename),
+        Box::new(|&v: &u16| ((v >> 8) as u8 * 50, v as u8, 7)),
+    )
+    .scale(Scaling::Uniform(1))
+    .gridlines(Gridlines::Hide)
+    .build()?;
+
+    // 2 colors, a new color, then 400 colors
+    let grid = |cell: fn(u16, u16) -> u16| -> Vec<Vec<u16>> {
+        (0..20)
+            .map(|x| (0..20).map(|y| cell(x, y)).collect())
+            .collect()
+    };
+    let frames = [
+        grid(|x, _| x % 2),
+        grid(|x, _| x % 2 * 2),
+        grid(|x, y| x * 20 + y),
+    ];
+    for grid in &frames {
+        video.add_frame(grid)?;
+    }
+    video.close()?;
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(std::fs::File::open(&filename)?).unwrap();
+    // Only the colors of the first frame
+    assert_eq!(decoder.global_palette().unwrap(), &[0, 0, 7, 0, 1, 7]);
+
+    let mut palettes = Vec::new();
+    let mut pixels = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        palettes.push(frame.palette.as_ref().map(|palette| palette.len() / 3));
+        pixels.push(frame.buffer.to_vec());
+    }
+    assert_eq!(palettes, vec![None, Some(2), Some(256)]);
+    // Frames with 256 colors or fewer are lossless
+    let expected = |x: usize| {
+        if x % 2 == 1 {
+            [0, 2, 7, 255]
+        } else {
+            [0, 0, 7, 255]
+        }
+    };
+    for (i, pixel) in pixels[1].chunks_exact(4).enumerate() {
+        assert_eq!(pixel, expected(i % 20));
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "png")]
+#[test]
+fn png_sequence_output() -> Result<()> {
+    env_logger_init();
+
+    let directory = std::env::temp_dir().join("gridvid_png_sequence");
+    std::fs::remove_dir_all(&directory).ok();
+
+    let mut video = Encoder::with_backend(
+        gridvid::backend::PngSequence::new(&directory),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(3))
+    .gridlines(Gridlines::Hide)
+    .build()?;
+
+    let mut grid = vec![vec![GridItem::Off; 2]; 2];
+    video.add_frame(&grid)?;
+    grid[1][1] = GridItem::On;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let decoder = png::Decoder::new(std::fs::File::open(directory.join("frame_00002.png"))?);
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (6, 6));
+    // Upper-right element is switched on
+    assert_eq!(&pixels[15..18], &[128, 0, 255]);
+    assert_eq!(&pixels[0..3], &[0, 0, 0]);
+    assert!(!directory.join("frame_00003.png").exists());
+
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[test]
+fn y4m_output() -> Result<()> {
+    env_logger_init();
+
+    let filename = TempPath::new(&"y4m_output.y4m");
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Y4m::new(&filename),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(4))
+    .gridlines(Gridlines::Hide)
+    .frame_rate(25000, 1001)
+    .build()?;
+
+    let grid = vec![vec![GridItem::Off; 2]; 3];
+    video.add_frame(&grid)?;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let output = std::fs::read(&filename)?;
+    let header = b"YUV4MPEG2 W12 H8 F25000:1001 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+    assert!(output.starts_with(header));
+
+    let frame_size = b"FRAME\n".len() + 12 * 8 * 3 / 2;
+    assert_eq!(output.len(), header.len() + 2 * frame_size);
+    let frame = &output[header.len()..][..frame_size];
+    assert!(frame.starts_with(b"FRAME\n"));
+    // Black in limited range luma
+    assert_eq!(frame[6], 16);
+
+    Ok(())
+}
+
+#[test]
+fn yuv_frames_need_even_dimensions() -> Result<()> {
+    env_logger_init();
+
+    /// Accepts any frame size in `init`.
+    struct YuvBackend;
+
+    impl VideoBackend for YuvBackend {
+        fn frame_format(&self) -> FrameFormat {
+            FrameFormat::Yuv420
+        }
+
+        fn init(&mut self, _info: &VideoInfo) -> Result<()> {
+            Ok(())
+        }
+
+        fn write_frame(&mut self, _yuv: &[u8], _duration: Duration) -> Result<()> {
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut video = Encoder::with_backend(YuvBackend, Box::new(griditem_to_rgb))
+        .scale(Scaling::Uniform(1))
+        .gridlines(Gridlines::Hide)
+        .build()?;
+    // 3x3 pixels, then a single row of pixels
+    for (width, height) in [(3, 3), (4, 1)] {
+        let grid = vec![vec![GridItem::On; height]; width];
+        assert!(matches!(
+            video.add_frame(&grid),
+            Err(gridvid::Error::InvalidFrameDimensions(size)) if size == (width, height)
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn yuv_frames_match_rgb_conversion() -> Result<()> {
+    env_logger_init();
+
+    // Odd scaling and gridlines put element and gridline edges inside 2x2 chroma blocks.
+    let grid: Vec<Vec<u8>> = (0..6)
+        .map(|x| (0..40).map(|y| ((x * 40 + y) * 7) as u8).collect())
+        .collect();
+    let converter = |v: &u8| (*v, 255 - *v, v.wrapping_mul(7));
+    let (scale, gridlines) = (Scaling::Uniform(3), Gridlines::Show((200, 30, 90)));
+
+    let filename = TempPath::new(&"yuv_frames_match_rgb_conversion.y4m");
+    let mut video =
+        Encoder::with_backend(gridvid::backend::Y4m::new(&filename), Box::new(converter))
+            .scale(scale)
+            .gridlines(gridlines)
+            .build()?;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let snapshot = gridvid::snapshot(&grid, converter, scale, gridlines)?;
+    let (width, height) = (snapshot.width(), snapshot.height());
+    let expected = YUVBuffer::with_rgb(width, height, snapshot.as_rgb());
+
+    let output = std::fs::read(&filename)?;
+    let frame = &output[output.windows(6).position(|w| w == b"FRAME\n").unwrap() + 6..];
+    let (y, uv) = frame.split_at(width * height);
+    let (u, v) = uv.split_at(width * height / 4);
+    assert_eq!(y, expected.y());
+    assert_eq!(u, expected.u());
+    assert_eq!(v, expected.v());
+
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_frames_match_sequential_frames() -> Result<()> {
+    env_logger_init();
+
+    // Tall enough to be split into several bands
+    let grid: Vec<Vec<u8>> = (0..6)
+        .map(|x| (0..40).map(|y| ((x * 40 + y) * 7) as u8).collect())
+        .collect();
+    let converter = |v: &u8| (*v, 255 - *v, v.wrapping_mul(7));
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()
+        .unwrap();
+
+    let build = |builder: EncoderBuilder<u8, gridvid::SyncConverter<u8>>| {
+        builder
+            .scale(Scaling::Uniform(3))
+            .gridlines(Gridlines::Show((200, 30, 90)))
+            .fps(10)
+            .build()
+    };
+
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    let hold = Duration::from_secs(2);
+    pool.install(|| {
+        let backend = RecordingBackend(Arc::clone(&recorded));
+        let mut video = build(Encoder::with_backend_par(backend, Box::new(converter)))?;
+        video.add_frame(&grid)?;
+        video.add_frame_par(&grid)?;
+        video.add_frame_par_with_duration(&grid, hold)?;
+        video.close()
+    })?;
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.frames[0], recorded.frames[1]);
+    assert_eq!(recorded.frames[0], recorded.frames[2]);
+    assert_eq!(recorded.durations[1..], [Duration::from_millis(100), hold]);
+
+    let filename = TempPath::new(&"parallel_frames_match_sequential_frames.y4m");
+    pool.install(|| {
+        let backend = gridvid::backend::Y4m::new(&filename);
+        let mut video = build(Encoder::with_backend_par(backend, Box::new(converter)))?;
+        video.add_frame(&grid)?;
+        video.add_frame_par(&grid)?;
+        video.close()
+    })?;
+    let output = std::fs::read(&filename)?;
+    let start = output.windows(6).position(|w| w == b"FRAME\n").unwrap();
+    let frame_size = b"FRAME\n".len() + (6 * 5 - 2) * (40 * 5 - 2) * 3 / 2;
+    let frames: Vec<&[u8]> = output[start..].chunks(frame_size).collect();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0], frames[1]);
+
+    Ok(())
+}
+
+/// Writer that can be inspected after the encoder takes ownership of a clone.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn h264_stream_to_writer() -> Result<()> {
+    env_logger_init();
+
+    let buffer = SharedBuffer::default();
+    let mut video = Encoder::with_backend(
+        gridvid::backend::H264::from_writer(buffer.clone()),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(8))
+    .build()?;
+
+    let grid = vec![vec![GridItem::On; 4]; 4];
+    video.add_frame(&grid)?;
+    let written = buffer.0.lock().unwrap().len();
+    assert!(written > 0);
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let stream = buffer.0.lock().unwrap();
+    assert!(stream.len() > written);
+    // Annex-B start code
+    assert!(stream.starts_with(&[0, 0, 0, 1]));
+
+    Ok(())
+}
+
+#[test]
+fn mp4_in_memory() -> Result<()> {
+    env_logger_init();
+
+    let mut video = Encoder::in_memory(Box::new(griditem_to_rgb)).build()?;
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    video.add_frame(&grid)?;
+    video.add_frame(&grid)?;
+    let mp4 = video.close_to_vec()?;
+
+    // First box of an MP4 file is `ftyp`
+    assert_eq!(&mp4[4..8], b"ftyp");
+
+    Ok(())
+}
+
+#[test]
+fn close_to_vec_needs_memory_backend() -> Result<()> {
+    let recorded = Arc::new(Mutex::new(Recorded::default()));
+    let mut video = Encoder::with_backend(
+        RecordingBackend(Arc::clone(&recorded)),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    video.add_frame(&vec![vec![GridItem::On; 2]; 2])?;
+    let result = video.close_to_vec();
+    assert!(matches!(
+        result,
+        Err(gridvid::Error::IoError(e)) if e.kind() == std::io::ErrorKind::Unsupported
+    ));
+    // The output is not finished, so that a file backend is not left behind as a complete video
+    assert!(!recorded.lock().unwrap().finished);
+
+    Ok(())
+}
+
+#[test]
+fn background_matches_direct_encoding() -> Result<()> {
+    env_logger_init();
+
+    let grids: Vec<_> = (0..6)
+        .map(|i| {
+            let mut grid = vec![vec![GridItem::Off; 8]; 8];
+            grid[i][7 - i] = GridItem::On;
+            grid
+        })
+        .collect();
+
+    let mut direct = Encoder::in_memory(Box::new(griditem_to_rgb)).build()?;
+    let mut background = Encoder::with_backend(
+        gridvid::backend::Background::new(gridvid::backend::Mp4::in_memory).queue_len(1),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    for grid in &grids {
+        direct.add_frame(grid)?;
+        background.add_frame(grid)?;
+    }
+    background.force_keyframe();
+    direct.force_keyframe();
+    direct.add_frame(&grids[0])?;
+    background.add_frame(&grids[0])?;
+
+    assert_eq!(background.close_to_vec()?, direct.close_to_vec()?);
+
+    Ok(())
+}
+
+#[test]
+fn parallel_segments_start_with_keyframes() -> Result<()> {
+    env_logger_init();
+
+    let grids: Vec<_> = (0..7)
+        .map(|i| {
+            let mut grid = vec![vec![GridItem::Off; 8]; 8];
+            grid[i][7 - i] = GridItem::On;
+            grid
+        })
+        .collect();
+    let encode = |segment_len: Option<usize>| -> Result<Vec<u8>> {
+        let mut video = Encoder::with_backend(
+            gridvid::backend::Mp4::in_memory().parallel_segments(segment_len),
+            Box::new(griditem_to_rgb),
+        )
+        .build()?;
+        for grid in &grids {
+            video.add_frame(grid)?;
+        }
+        video.close_to_vec()
+    };
+
+    // A single segment is encoded exactly like the default
+    let direct = encode(None)?;
+    assert_eq!(encode(Some(100))?, direct);
+
+    let segmented = encode(Some(3))?;
+    assert_eq!(
+        mp4_sample_durations(&segmented),
+        mp4_sample_durations(&direct)
+    );
+    assert_eq!(mp4_sync_samples(&segmented), [1, 4, 7]);
+
+    Ok(())
+}
+
+#[test]
+fn parallel_segment_errors_are_returned() -> Result<()> {
+    env_logger_init();
+
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Mp4::from_writer(FailingWriter(100)).parallel_segments(Some(1)),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    let grid = vec![vec![GridItem::On; 8]; 8];
+
+    // Segments are muxed once more are encoding than there are CPU cores
+    match (0..64).find_map(|_| video.add_frame(&grid).err()) {
+        Some(gridvid::Error::IoError(e)) => assert_eq!(e.to_string(), "disk full"),
+        Some(e) => panic!("expected write error, got {e:?}"),
+        None => panic!("expected write error"),
+    }
+    // Later frames are not muxed after a missing segment
+    assert!(video.add_frame(&grid).is_err());
+    assert!(video.close().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn encoder_moves_between_threads() -> Result<()> {
+    env_logger_init();
+
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    let mut video = Encoder::with_backend_send(
+        gridvid::backend::Background::new(gridvid::backend::Mp4::in_memory),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    video.add_frame(&grid)?;
+
+    let worker = std::thread::spawn(move || -> Result<Vec<u8>> {
+        video.add_frame(&grid)?;
+        video.close_to_vec()
+    });
+    let mp4 = worker.join().unwrap()?;
+    assert_eq!(mp4_sample_durations(&mp4).len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn background_errors_are_returned() -> Result<()> {
+    env_logger_init();
+
+    // Errors from opening the output are returned by `build`
+    let filename = TempPath::new(&"background_errors_are_returned.mp4");
+    std::fs::write(&filename, b"")?;
+    let path = filename.0.clone();
+    let build = Encoder::with_backend(
+        gridvid::backend::Background::new(move || gridvid::backend::Mp4::new(path)),
+        Box::new(griditem_to_rgb),
+    )
+    .build();
+    assert!(matches!(build, Err(gridvid::Error::IoError(_))));
+
+    // Later errors are returned by the next `add_frame` or by `close`
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Background::new(|| {
+            gridvid::backend::Mp4::from_writer(FailingWriter(100))
+        }),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    let res = (0..3)
+        .try_for_each(|_| video.add_frame(&grid).map(|_| ()))
+        .and_then(|_| video.close());
+    match res {
+        Err(gridvid::Error::IoError(e)) => assert_eq!(e.to_string(), "disk full"),
+        res => panic!("expected write error, got {res:?}"),
+    }
+
+    // After `add_frame` returns the worker's error, later calls fail instead of panicking
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Background::new(|| {
+            gridvid::backend::Mp4::from_writer(FailingWriter(100))
+        })
+        .queue_len(1),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    let failed = (0..50).find_map(|_| video.add_frame(&grid).err());
+    assert!(matches!(failed, Some(gridvid::Error::IoError(_))));
+    assert!(video.add_frame(&grid).is_err());
+    assert!(video.close().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn background_init_errors_are_returned() -> Result<()> {
+    env_logger_init();
+
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Background::new(gridvid::backend::Mp4::in_memory),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(1))
+    .build()?;
+    // 7x7 pixels with gridlines, which H.264 cannot encode
+    let grid = vec![vec![GridItem::On; 3]; 3];
+    for _ in 0..2 {
+        assert!(matches!(
+            video.add_frame(&grid),
+            Err(gridvid::Error::InvalidFrameDimensions((7, 7)))
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the sample durations from the `stts` box of an MP4 file.
+fn mp4_sample_durations(mp4: &[u8]) -> Vec<u32> {
+    let read_u32 = |pos: usize| u32::from_be_bytes(mp4[pos..pos + 4].try_into().unwrap());
+    let stts = mp4.windows(4).position(|w| w == b"stts").unwrap() + 4;
+
+    let mut durations = Vec::new();
+    for entry in 0..read_u32(stts + 4) as usize {
+        let pos = stts + 8 + entry * 8;
+        let count = read_u32(pos) as usize;
+        durations.resize(durations.len() + count, read_u32(pos + 4));
+    }
+    durations
+}
+
+/// Returns the 1-based keyframe sample numbers from the `stss` box of an MP4 file.
+fn mp4_sync_samples(mp4: &[u8]) -> Vec<u32> {
+    let read_u32 = |pos: usize| u32::from_be_bytes(mp4[pos..pos + 4].try_into().unwrap());
+    let stss = mp4.windows(4).position(|w| w == b"stss").unwrap() + 4;
+
+    (0..read_u32(stss + 4) as usize)
+        .map(|entry| read_u32(stss + 8 + entry * 4))
+        .collect()
+}
+
+#[test]
+fn mp4_fractional_frame_rate() -> Result<()> {
+    env_logger_init();
+
+    let durations = |num: u32, den: u32, frames: usize| -> Result<Vec<u32>> {
+        let mut video = Encoder::in_memory(Box::new(griditem_to_rgb))
+            .frame_rate(num, den)
+            .build()?;
+        let grid = vec![vec![GridItem::On; 8]; 8];
+        for _ in 0..frames {
+            video.add_frame(&grid)?;
+        }
+        Ok(mp4_sample_durations(&video.close_to_vec()?))
+    };
+
+    // 7 fps does not divide the 90 kHz timescale, but 7 frames still last exactly one second
+    let seven_fps = durations(7, 1, 7)?;
+    assert_eq!(seven_fps.len(), 7);
+    assert_eq!(seven_fps.iter().sum::<u32>(), 90000);
+
+    assert_eq!(durations(1, 2, 2)?, vec![180000, 180000]);
+    assert_eq!(durations(30000, 1001, 3)?, vec![3003, 3003, 3003]);
+
+    Ok(())
+}
+
+#[test]
+fn low_bitrate_keeps_every_frame() -> Result<()> {
+    env_logger_init();
+
+    let mut video = Encoder::in_memory(Box::new(|&v: &u8| (v, v / 2, 255 - v)))
+        .scale(Scaling::Uniform(2))
+        .gridlines(Gridlines::Hide)
+        .fps(30)
+        .rate_control(RateControl::Bitrate)
+        .bitrate(1_000)
+        .build()?;
+    // Noisy frames cannot fit the bitrate, so OpenH264 would skip some of them
+    let mut seed = 1u32;
+    for _ in 0..30 {
+        let grid: Vec<Vec<u8>> = (0..64)
+            .map(|_| {
+                (0..64)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                        (seed >> 24) as u8
+                    })
+                    .collect()
+            })
+            .collect();
+        video.add_frame(&grid)?;
+    }
+
+    let durations = mp4_sample_durations(&video.close_to_vec()?);
+    assert_eq!(durations.len(), 30);
+    assert_eq!(durations.iter().sum::<u32>(), 90000);
+
+    Ok(())
+}
+
+#[test]
+fn per_frame_durations() -> Result<()> {
+    env_logger_init();
+
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    let mut video = Encoder::in_memory(Box::new(griditem_to_rgb))
+        .fps(4)
+        .build()?;
+    video.add_frame(&grid)?;
+    video.add_frame_with_duration(&grid, Duration::from_secs(2))?;
+    video.add_frame(&grid)?;
+    video.add_frame_with_duration(&grid, Duration::from_millis(100))?;
+    assert_eq!(
+        mp4_sample_durations(&video.close_to_vec()?),
+        vec![22500, 180000, 22500, 9000]
+    );
+
+    // Y4M repeats frames to fill their duration
+    let filename = TempPath::new(&"per_frame_durations.y4m");
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Y4m::new(&filename),
+        Box::new(griditem_to_rgb),
+    )
+    .scale(Scaling::Uniform(2))
+    .gridlines(Gridlines::Hide)
+    .fps(4)
+    .build()?;
+    video.add_frame(&grid)?;
+    video.add_frame_with_duration(&grid, Duration::from_secs(1))?;
+    video.close()?;
+
+    let output = std::fs::read(&filename)?;
+    assert_eq!(output.windows(6).filter(|w| w == b"FRAME\n").count(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn mp4_writer_matches_file() -> Result<()> {
+    env_logger_init();
+
+    let grid = vec![vec![GridItem::On; 8]; 8];
+
+    let filename = TempPath::new(&"mp4_writer_matches_file.mp4");
+    let mut video = Encoder::new(&filename, Box::new(griditem_to_rgb)).build()?;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    let written = TempPath::new(&"mp4_writer_matches_file_writer.mp4");
+    let file = std::fs::File::create(&written)?;
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Mp4::from_writer(file),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    assert_eq!(std::fs::read(&filename)?, std::fs::read(&written)?);
+
+    Ok(())
+}
+
+/// Seekable writer that fails after a number of bytes.
+struct FailingWriter(usize);
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() > self.0 {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.0 -= buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::io::Seek for FailingWriter {
+    fn seek(&mut self, _: std::io::SeekFrom) -> std::io::Result<u64> {
+        Ok(0)
+    }
+}
+
+#[test]
+fn mp4_write_error_is_returned() -> Result<()> {
+    env_logger_init();
+
+    for limit in [0, 100] {
+        let mut video = Encoder::with_backend(
+            gridvid::backend::Mp4::from_writer(FailingWriter(limit)),
+            Box::new(griditem_to_rgb),
+        )
+        .build()?;
+
+        // Frames are muxed as they are added, so the error may come from either call
+        let res = video
+            .add_frame(&vec![vec![GridItem::On; 8]; 8])
+            .and_then(|_| video.close());
+        match res {
+            Err(gridvid::Error::IoError(e)) => assert_eq!(e.to_string(), "disk full"),
+            res => panic!("expected write error, got {res:?}"),
+        }
+    }
+
+    // The header write fails when the first frame is added, and later frames fail too
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Mp4::from_writer(FailingWriter(0)),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    assert!(video.add_frame(&grid).is_err());
+    assert!(video.add_frame(&grid).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn fragmented_mp4_playable_without_close() -> Result<()> {
+    env_logger_init();
+
+    let filename = TempPath::new(&"fragmented_without_close.mp4");
+    let mut video = Encoder::with_backend(
+        gridvid::backend::Mp4::new(&filename).fragmented(true),
+        Box::new(griditem_to_rgb),
+    )
+    .build()?;
+
+    let grid = vec![vec![GridItem::On; 8]; 8];
+    video.add_frame(&grid)?;
+    video.add_frame(&grid)?;
+    // Simulate a crash: the encoder is never closed or dropped
+    std::mem::forget(video);
+
+    let output = std::fs::read(&filename)?;
+    let contains = |name: &[u8]| output.windows(4).any(|w| w == name);
+    assert!(contains(b"moov"));
+    assert_eq!(output.windows(4).filter(|w| w == b"moof").count(), 2);
+
+    Ok(())
+}
+
+fn mp4_size(configure: impl FnOnce(EncoderBuilder<u8>) -> EncoderBuilder<u8>) -> Result<usize> {
+    let mut video = configure(Encoder::in_memory(Box::new(|&v: &u8| (v, v / 2, 255 - v))))
+        .scale(Scaling::Uniform(4))
+        .build()?;
+    for frame in 0..4u32 {
+        let grid: Vec<Vec<u8>> = (0..32u32)
+            .map(|x| {
+                (0..32u32)
+                    .map(|y| ((x * 37 + y * 101 + frame * 53) % 251) as u8)
+                    .collect()
+            })
+            .collect();
+        video.add_frame(&grid)?;
+    }
+    Ok(video.close_to_vec()?.len())
+}
+
+#[test]
+fn h264_quality_settings() -> Result<()> {
+    env_logger_init();
+
+    let default = mp4_size(|builder| builder)?;
+    let near_lossless = mp4_size(|builder| builder.near_lossless())?;
+    let coarse = mp4_size(|builder| builder.rate_control(RateControl::ConstantQp(51)))?;
+    assert!(near_lossless > default);
+    assert!(coarse < near_lossless);
+
+    let fine_range = mp4_size(|builder| builder.qp_range(12, 20))?;
+    let coarse_range = mp4_size(|builder| builder.qp_range(40, 51))?;
+    assert!(fine_range > coarse_range);
+
+    let high_bitrate = mp4_size(|builder| {
+        builder
+            .rate_control(RateControl::Bitrate)
+            .bitrate(5_000_000)
+    })?;
+    let low_bitrate =
+        mp4_size(|builder| builder.rate_control(RateControl::Bitrate).bitrate(50_000))?;
+    assert!(high_bitrate > low_bitrate);
+
+    Ok(())
+}
+
+/// Counts the IDR slices in an Annex-B stream.
+fn count_idr_slices(stream: &[u8]) -> usize {
+    stream
+        .windows(4)
+        .filter(|w| w[..3] == [0, 0, 1] && w[3] & 0x1f == 5)
+        .count()
+}
+
+#[test]
+fn h264_keyframes() -> Result<()> {
+    env_logger_init();
+
+    let encode = |interval: u32, forced: &[usize]| -> Result<usize> {
+        let buffer = SharedBuffer::default();
+        let mut video = Encoder::with_backend(
+            gridvid::backend::H264::from_writer(buffer.clone()),
+            Box::new(griditem_to_rgb),
+        )
+        .scale(Scaling::Uniform(8))
+        .keyframe_interval(interval)
+        .build()?;
+
+        let grid = vec![vec![GridItem::On; 4]; 4];
+        for frame in 0..7 {
+            if forced.contains(&frame) {
+                video.force_keyframe();
+            }
+            video.add_frame(&grid)?;
+        }
+        video.close()?;
+
+        let stream = buffer.0.lock().unwrap();
+        Ok(count_idr_slices(&stream))
+    };
+
+    assert_eq!(encode(0, &[])?, 1);
+    // Frames 0, 3 and 6
+    assert_eq!(encode(3, &[])?, 3);
+    assert_eq!(encode(0, &[0, 4])?, 2);
+
+    Ok(())
+}
@@ -18,8 +18,10 @@ pub fn griditem_to_rgb(item: &GridItem) -> gridvid::Rgb {
     }
 }
 
+#[allow(unused)]
 #[derive(Debug)]
 pub struct TempPath(pub PathBuf);
+#[allow(unused)]
 impl TempPath {
     pub fn new<P: AsRef<Path>>(filename: &P) -> Self {
         let path = std::env::temp_dir().join(filename);
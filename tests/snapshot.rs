@@ -0,0 +1,225 @@
+mod common;
+use common::*;
+
+use gridvid::{snapshot, Encoder, FlatGrid, Gridlines, Layout, Origin, Result, Scaling, ToRgb};
+
+#[test]
+fn snapshot_matches_layout() -> Result<()> {
+    env_logger_init();
+
+    let mut grid = vec![vec![GridItem::Off; 3]; 2];
+    grid[1][2] = GridItem::On;
+
+    let image = snapshot(
+        &grid,
+        griditem_to_rgb,
+        Scaling::Uniform(2),
+        Gridlines::Show((255, 255, 255)),
+    )?;
+    assert_eq!((image.width(), image.height()), (2 * 2 + 2, 3 * 2 + 4));
+
+    let pixel = |x: usize, y: usize| &image.as_rgb()[(y * image.width() + x) * 3..][..3];
+    // Upper-right element, gridline and lower-left element
+    assert_eq!(pixel(5, 0), &[128, 0, 255]);
+    assert_eq!(pixel(2, 0), &[255, 255, 255]);
+    assert_eq!(pixel(0, 9), &[0, 0, 0]);
+
+    let mut ppm = Vec::new();
+    image.write_ppm(&mut ppm)?;
+    assert!(ppm.starts_with(b"P6\n6 10\n255\n"));
+    assert_eq!(ppm.len(), 12 + 6 * 10 * 3);
+
+    Ok(())
+}
+
+#[test]
+fn encoder_snapshot_uses_encoder_options() -> Result<()> {
+    env_logger_init();
+
+    let filename = TempPath::new(&"encoder_snapshot.mp4");
+    let video = Encoder::new(&filename, Box::new(griditem_to_rgb))
+        .scale(Scaling::MaxSize(100, 50))
+        .gridlines(Gridlines::Hide)
+        .build()?;
+
+    let grid = vec![vec![GridItem::On; 5]; 10];
+    let image = video.snapshot(&grid)?;
+    assert_eq!((image.width(), image.height()), (100, 50));
+
+    Ok(())
+}
+
+#[test]
+fn grid_sources_render_identically() -> Result<()> {
+    env_logger_init();
+
+    let render = |grid: &dyn Fn(usize, usize) -> u8| -> Result<Vec<u8>> {
+        let convert = |&v: &u8| (v, v, v);
+        let nested: Vec<Vec<u8>> = (0..4)
+            .map(|x| (0..3).map(|y| grid(x, y)).collect())
+            .collect();
+        let array: [[u8; 3]; 4] = std::array::from_fn(|x| std::array::from_fn(|y| grid(x, y)));
+        let flat: Vec<u8> = (0..3)
+            .flat_map(|y| (0..4).map(move |x| grid(x, y)))
+            .collect();
+
+        let expected = snapshot(&nested, convert, Scaling::Uniform(2), Gridlines::Hide)?;
+        assert_eq!(
+            snapshot(&array, convert, Scaling::Uniform(2), Gridlines::Hide)?,
+            expected
+        );
+        assert_eq!(
+            snapshot(&array[..], convert, Scaling::Uniform(2), Gridlines::Hide)?,
+            expected
+        );
+        assert_eq!(
+            snapshot(
+                &FlatGrid::new(&flat, 4),
+                convert,
+                Scaling::Uniform(2),
+                Gridlines::Hide
+            )?,
+            expected
+        );
+        Ok(expected.as_rgb().to_vec())
+    };
+
+    let rgb = render(&|x, y| (x * 10 + y) as u8)?;
+    // Bottom-left element is (0, 0), in the first column of the last row
+    assert_eq!(rgb[(5 * 8) * 3], 0);
+    assert_eq!(rgb[(5 * 8 + 7) * 3], 30);
+    assert_eq!(rgb[7 * 3], 32);
+
+    Ok(())
+}
+
+#[test]
+fn layout_and_origin() -> Result<()> {
+    env_logger_init();
+
+    // Element shown in column `x` from the left and row `y` from the top of a 3x2 frame
+    let shown = |x: usize, y: usize| (y * 3 + x) as u8;
+    let expected = snapshot(
+        &(0..3)
+            .map(|x| (0..2).rev().map(|y| shown(x, y)).collect())
+            .collect::<Vec<Vec<u8>>>(),
+        |&v: &u8| (v, v, v),
+        Scaling::Uniform(1),
+        Gridlines::Hide,
+    )?;
+
+    for origin in [
+        Origin::BottomLeft,
+        Origin::TopLeft,
+        Origin::BottomRight,
+        Origin::TopRight,
+    ] {
+        // Column and row of the frame where grid column `i` and row `j` are shown
+        let place = |i: usize, j: usize| match origin {
+            Origin::BottomLeft => (i, 1 - j),
+            Origin::TopLeft => (i, j),
+            Origin::BottomRight => (2 - i, 1 - j),
+            Origin::TopRight => (2 - i, j),
+        };
+        let columns: Vec<Vec<u8>> = (0..3)
+            .map(|i| {
+                (0..2)
+                    .map(|j| shown(place(i, j).0, place(i, j).1))
+                    .collect()
+            })
+            .collect();
+        let rows: Vec<Vec<u8>> = (0..2)
+            .map(|j| (0..3).map(|i| columns[i][j]).collect())
+            .collect();
+
+        for (layout, grid) in [(Layout::ColumnMajor, &columns), (Layout::RowMajor, &rows)] {
+            let video = Encoder::in_memory(Box::new(|&v: &u8| (v, v, v)))
+                .scale(Scaling::Uniform(1))
+                .gridlines(Gridlines::Hide)
+                .layout(layout)
+                .origin(origin)
+                .build()?;
+            assert_eq!(video.snapshot(grid)?, expected, "{layout:?} {origin:?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn built_in_colors() -> Result<()> {
+    env_logger_init();
+
+    assert_eq!(true.to_rgb(), (255, 255, 255));
+    assert_eq!(false.to_rgb(), (0, 0, 0));
+    assert_eq!(7u8.to_rgb(), (7, 7, 7));
+    assert_eq!(u16::MAX.to_rgb(), (255, 255, 255));
+    assert_eq!(257u16.to_rgb(), (1, 1, 1));
+    assert_eq!(0.5f32.to_rgb(), (128, 128, 128));
+    assert_eq!(f32::NAN.to_rgb(), (0, 0, 0));
+    assert_eq!((-1.0f64).to_rgb(), (0, 0, 0));
+    assert_eq!(2.0f64.to_rgb(), (255, 255, 255));
+    assert_eq!((1, 2, 3).to_rgb(), (1, 2, 3));
+    assert_eq!([1, 2, 3].to_rgb(), (1, 2, 3));
+    assert_eq!([1, 2, 3, 0].to_rgb(), (1, 2, 3));
+
+    let filename = TempPath::new(&"new_default.mp4");
+    let mut video = Encoder::new_default(&filename)
+        .scale(Scaling::Uniform(8))
+        .gridlines(Gridlines::Hide)
+        .build()?;
+    let grid = [[0.0f32, 1.0], [1.0, 0.0]];
+    let image = video.snapshot(&grid)?;
+    assert_eq!(&image.as_rgb()[..3], &[255, 255, 255]);
+    assert_eq!(
+        &image.as_rgb()[image.as_rgb().len() - 3..],
+        &[255, 255, 255]
+    );
+    video.add_frame(&grid)?;
+    video.close()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn ndarray_grids() -> Result<()> {
+    use ndarray::{s, Array2};
+
+    env_logger_init();
+
+    let convert = |&v: &u8| (v, v, v);
+    let render = |grid: &dyn Fn(usize, usize) -> u8| {
+        let nested: Vec<Vec<u8>> = (0..4)
+            .map(|x| (0..6).map(|y| grid(x, y)).collect())
+            .collect();
+        snapshot(&nested, convert, Scaling::Uniform(1), Gridlines::Hide)
+    };
+
+    let array = Array2::from_shape_fn((4, 6), |(x, y)| (x * 10 + y) as u8);
+    assert_eq!(
+        snapshot(&array, convert, Scaling::Uniform(1), Gridlines::Hide)?,
+        render(&|x, y| array[[x, y]])?
+    );
+
+    // Views are read through their strides
+    let reversed = array.slice(s![.., ..;-1]);
+    assert_eq!(
+        snapshot(&reversed, convert, Scaling::Uniform(1), Gridlines::Hide)?,
+        render(&|x, y| array[[x, 5 - y]])?
+    );
+
+    // A transposed view in row-major layout shows the original array
+    let mut video = Encoder::in_memory(Box::new(convert))
+        .scale(Scaling::Uniform(4))
+        .gridlines(Gridlines::Hide)
+        .layout(Layout::RowMajor)
+        .build()?;
+    assert_eq!(
+        video.snapshot(&array.t())?,
+        snapshot(&array, convert, Scaling::Uniform(4), Gridlines::Hide)?
+    );
+    video.add_frame(&array.t())?;
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 mod common;
 use common::*;
 
-use gridvid::{Encoder, Error, Gridlines, Result, Scaling};
+use gridvid::{Encoder, Error, FlatGrid, Gridlines, Result, Scaling};
 
 #[test]
 fn file_overwrite_prevention() -> Result<()> {
@@ -24,6 +24,31 @@ fn file_overwrite_prevention() -> Result<()> {
     )))
 }
 
+#[test]
+fn inconsistent_grid_shape() -> Result<()> {
+    env_logger_init();
+
+    let mut video = Encoder::in_memory(Box::new(griditem_to_rgb)).build()?;
+    let mut grid = vec![vec![GridItem::Off; 4]; 4];
+    grid[2].pop();
+    assert!(matches!(
+        video.add_frame(&grid),
+        Err(Error::InconsistentGridHeight(0))
+    ));
+
+    let cells = vec![GridItem::Off; 15];
+    assert!(matches!(
+        video.add_frame(&FlatGrid::new(&cells, 4)),
+        Err(Error::InconsistentGridHeight(0))
+    ));
+    assert!(matches!(
+        video.add_frame(&FlatGrid::new(&cells, 0)),
+        Err(Error::InvalidFrameDimensions(_))
+    ));
+
+    Ok(())
+}
+
 #[test]
 // Ignored by default because it takes disproportionately long
 #[ignore]